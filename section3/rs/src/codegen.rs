@@ -1,39 +1,149 @@
 extern crate llvm_sys;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{CStr, CString};
+use std::fmt;
 
+use llvm_sys::analysis::*;
 use llvm_sys::core::*;
 use llvm_sys::prelude::*;
 use llvm_sys::target::{
-    LLVM_InitializeAllAsmParsers, LLVM_InitializeAllAsmPrinters, LLVM_InitializeAllTargetInfos,
-    LLVM_InitializeAllTargetMCs, LLVM_InitializeAllTargets,
+    LLVMDisposeTargetData, LLVMSetModuleDataLayout, LLVM_InitializeAllAsmParsers,
+    LLVM_InitializeAllAsmPrinters, LLVM_InitializeAllTargetInfos, LLVM_InitializeAllTargetMCs,
+    LLVM_InitializeAllTargets,
 };
 use llvm_sys::target_machine::*;
+use llvm_sys::transforms::pass_builder::*;
 use llvm_sys::LLVMIntPredicate::*;
+use llvm_sys::LLVMLinkage;
+use llvm_sys::LLVMTypeKind;
 
 use super::constants::*;
-use super::parser::{Expr, Function, Program, Type};
+use super::parser::{Deparse, Expr, Function, Global, Item, Program, Type};
+use super::span::Span;
 
-fn cstr(s: &str) -> Cow<CStr> {
+#[derive(Clone, Debug, PartialEq)]
+pub enum CodegenError {
+    // the AST could not be lowered, e.g. an unknown name
+    Semantic {
+        message: String,
+        span: Span,
+    },
+    // the generated module failed the LLVM verifier
+    Verify {
+        function: Option<String>,
+        message: String,
+    },
+    UnknownTarget {
+        triple: String,
+        message: String,
+    },
+    Optimize {
+        message: String,
+    },
+    // the backend could not generate code for the target
+    Compile {
+        triple: String,
+        message: String,
+    },
+    Emit {
+        path: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::Semantic { message, .. } => write!(f, "{}", message),
+            CodegenError::Verify {
+                function: Some(function),
+                message,
+            } => write!(f, "Invalid IR in function `{}`: {}", function, message),
+            CodegenError::Verify {
+                function: None,
+                message,
+            } => write!(f, "Invalid IR in module: {}", message),
+            CodegenError::UnknownTarget { triple, message } => {
+                write!(f, "Unknown target `{}`: {}", triple, message)
+            }
+            CodegenError::Optimize { message } => write!(f, "Optimization failed: {}", message),
+            CodegenError::Compile { triple, message } => {
+                write!(f, "Unable to compile for `{}`: {}", triple, message)
+            }
+            CodegenError::Emit { path, message } => {
+                write!(f, "Unable to emit `{}`: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+impl CodegenError {
+    // the source the error points at, if it comes from the program
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CodegenError::Semantic { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+fn semantic<T>(span: Span, message: String) -> Result<T, CodegenError> {
+    Err(CodegenError::Semantic { message, span })
+}
+
+// take ownership of a message allocated by LLVM
+unsafe fn take_message(msg: *mut std::os::raw::c_char) -> String {
+    if msg.is_null() {
+        return String::new();
+    }
+    let text = CStr::from_ptr(msg).to_string_lossy().trim().to_string();
+    LLVMDisposeMessage(msg);
+    text
+}
+
+fn cstr(s: &str) -> Cow<'_, CStr> {
     Cow::from(CString::new(s).expect("works"))
 }
 
 struct Scoped {
-    ty: LLVMTypeRef,
+    ty: Type,
     val: LLVMValueRef,
 }
 
+// where `break` and `continue` go in the innermost loop or switch, a switch
+// takes `continue` from the loop around it
+struct Loop {
+    break_bb: LLVMBasicBlockRef,
+    continue_bb: Option<LLVMBasicBlockRef>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
 struct LLVM {
     ctx: LLVMContextRef,
     builder: LLVMBuilderRef,
     module: LLVMModuleRef,
-    locals: HashMap<String, Scoped>,
+    scopes: Vec<HashMap<String, Scoped>>,
+    // the return types of every declared function
+    functions: HashMap<String, Type>,
+    loops: Vec<Loop>,
     func: LLVMValueRef,
     ret_block: LLVMBasicBlockRef,
     ret_val: LLVMValueRef,
 }
 
+impl Drop for LLVM {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeBuilder(self.builder);
+            LLVMDisposeModule(self.module);
+            LLVMContextDispose(self.ctx);
+        }
+    }
+}
+
 impl LLVM {
     fn new() -> LLVM {
         let name = cstr("my cool jit");
@@ -45,145 +155,821 @@ impl LLVM {
                 ctx,
                 builder,
                 module,
-                locals: HashMap::new(),
+                scopes: Vec::new(),
+                functions: HashMap::new(),
+                loops: Vec::new(),
                 func: std::ptr::null_mut(),
                 ret_block: std::ptr::null_mut(),
                 ret_val: std::ptr::null_mut(),
             }
         }
     }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &str, scoped: Scoped) {
+        self.scopes
+            .last_mut()
+            .expect("declaration outside of any scope")
+            .insert(name.to_string(), scoped);
+    }
+
+    // allocas go to the top of the entry block so locals declared in a loop
+    // body do not grow the stack on every iteration
+    fn alloca(&self, ty: LLVMTypeRef, name: &str) -> LLVMValueRef {
+        unsafe {
+            let builder = LLVMCreateBuilderInContext(self.ctx);
+            let entry = LLVMGetEntryBasicBlock(self.func);
+            let first = LLVMGetFirstInstruction(entry);
+            if first.is_null() {
+                LLVMPositionBuilderAtEnd(builder, entry);
+            } else {
+                LLVMPositionBuilderBefore(builder, first);
+            }
+            let val = LLVMBuildAlloca(builder, ty, cstr(name).as_ptr());
+            LLVMDisposeBuilder(builder);
+            val
+        }
+    }
+
+    // whether the current block already ends in a branch or return, in
+    // which case nothing more may be emitted into it
+    fn is_terminated(&self) -> bool {
+        unsafe { !LLVMGetBasicBlockTerminator(LLVMGetInsertBlock(self.builder)).is_null() }
+    }
+
+    // fall through to `bb` unless control already left the current block
+    fn branch_to(&self, bb: LLVMBasicBlockRef) {
+        if !self.is_terminated() {
+            unsafe { LLVMBuildBr(self.builder, bb) };
+        }
+    }
+
+    fn llvm_type(&self, ty: &Type) -> LLVMTypeRef {
+        match ty {
+            Type::Int => unsafe { LLVMInt32TypeInContext(self.ctx) },
+            Type::Void => unsafe { LLVMVoidTypeInContext(self.ctx) },
+            Type::Char | Type::UChar => unsafe { LLVMInt8TypeInContext(self.ctx) },
+            Type::Short | Type::UShort => unsafe { LLVMInt16TypeInContext(self.ctx) },
+            Type::UInt | Type::Long | Type::ULong => unsafe { LLVMInt32TypeInContext(self.ctx) },
+            Type::Pointer(to) => unsafe { LLVMPointerType(self.element_type(to), 0) },
+            Type::Array(elem, len) => unsafe { LLVMArrayType(self.llvm_type(elem), *len) },
+            Type::Volatile(ty) | Type::Const(ty) => self.llvm_type(ty),
+        }
+    }
+
+    // what a pointer steps over, `void*` moves by bytes
+    fn element_type(&self, to: &Type) -> LLVMTypeRef {
+        if to.is_void() {
+            return unsafe { LLVMInt8TypeInContext(self.ctx) };
+        }
+        self.llvm_type(to)
+    }
+
+    // values narrower than int are widened as soon as they are loaded
+    fn value_type(&self, ty: &Type) -> LLVMTypeRef {
+        match ty.int_bits() {
+            Some(_) => unsafe { LLVMInt32TypeInContext(self.ctx) },
+            None => self.llvm_type(ty.unqualified().decay().unqualified()),
+        }
+    }
+
+    // widen a value stored as `ty` to the value it has in arithmetic
+    fn widen(&self, val: LLVMValueRef, ty: &Type) -> LLVMValueRef {
+        let int = unsafe { LLVMInt32TypeInContext(self.ctx) };
+        let name = cstr("widetmp");
+        unsafe {
+            match ty.int_bits() {
+                Some(bits) if bits < 32 && ty.is_unsigned() => {
+                    LLVMBuildZExt(self.builder, val, int, name.as_ptr())
+                }
+                Some(bits) if bits < 32 => LLVMBuildSExt(self.builder, val, int, name.as_ptr()),
+                _ => self.convert(val, self.value_type(ty)),
+            }
+        }
+    }
+
+    fn is_bool(val: LLVMValueRef) -> bool {
+        unsafe {
+            let ty = LLVMTypeOf(val);
+            LLVMGetTypeKind(ty) == LLVMTypeKind::LLVMIntegerTypeKind && LLVMGetIntTypeWidth(ty) == 1
+        }
+    }
+
+    fn to_int(&self, val: LLVMValueRef) -> LLVMValueRef {
+        if !LLVM::is_bool(val) {
+            return val;
+        }
+        unsafe {
+            LLVMBuildZExt(
+                self.builder,
+                val,
+                LLVMInt32TypeInContext(self.ctx),
+                cstr("booltmp").as_ptr(),
+            )
+        }
+    }
+
+    fn to_bool(&self, val: LLVMValueRef) -> LLVMValueRef {
+        if LLVM::is_bool(val) {
+            return val;
+        }
+        unsafe {
+            LLVMBuildICmp(
+                self.builder,
+                LLVMIntNE,
+                val,
+                LLVMConstNull(LLVMTypeOf(val)),
+                cstr("tobool").as_ptr(),
+            )
+        }
+    }
+
+    // the implicit conversions between ints of any width and pointers
+    fn convert(&self, val: LLVMValueRef, ty: LLVMTypeRef) -> LLVMValueRef {
+        let val = self.to_int(val);
+        let from = unsafe { LLVMTypeOf(val) };
+        if from == ty {
+            return val;
+        }
+        let name = cstr("convtmp");
+        let is_pointer = |ty| unsafe { LLVMGetTypeKind(ty) } == LLVMTypeKind::LLVMPointerTypeKind;
+        unsafe {
+            match (is_pointer(from), is_pointer(ty)) {
+                (true, true) => LLVMBuildBitCast(self.builder, val, ty, name.as_ptr()),
+                (true, false) => LLVMBuildPtrToInt(self.builder, val, ty, name.as_ptr()),
+                (false, true) => LLVMBuildIntToPtr(self.builder, val, ty, name.as_ptr()),
+                (false, false) if LLVMGetIntTypeWidth(from) > LLVMGetIntTypeWidth(ty) => {
+                    LLVMBuildTrunc(self.builder, val, ty, name.as_ptr())
+                }
+                // narrower C integers are widened with their own signedness
+                // as they are loaded, anything else narrower is unsigned
+                (false, false) => LLVMBuildZExt(self.builder, val, ty, name.as_ptr()),
+            }
+        }
+    }
+
+    // innermost declaration wins, so inner blocks shadow outer ones
+    fn lookup(&self, name: &str, span: Span) -> Result<&Scoped, CodegenError> {
+        match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            Some(scoped) => Ok(scoped),
+            None => semantic(span, format!("Unknown variable `{}`", name)),
+        }
+    }
+
+    // load the value of a `ty` lvalue, only volatile ones may be memory
+    // mapped IO and must be accessed exactly as written
+    fn build_load(&self, ptr: LLVMValueRef, ty: &Type, name: &str) -> LLVMValueRef {
+        // the value of an array is the address of its first element
+        if let Type::Array(..) = ty.unqualified() {
+            return self.build_element(ptr, ty, 0);
+        }
+        unsafe {
+            let val = LLVMBuildLoad2(self.builder, self.llvm_type(ty), ptr, cstr(name).as_ptr());
+            if ty.is_volatile() {
+                LLVMSetVolatile(val, 1);
+            }
+            self.widen(val, ty)
+        }
+    }
+
+    // returns the value as it reads back, e.g. truncated to a char
+    fn build_store(&self, val: LLVMValueRef, ptr: LLVMValueRef, ty: &Type) -> LLVMValueRef {
+        let stored = self.convert(val, self.llvm_type(ty));
+        unsafe {
+            let store = LLVMBuildStore(self.builder, stored, ptr);
+            if ty.is_volatile() {
+                LLVMSetVolatile(store, 1);
+            }
+        }
+        self.widen(stored, ty)
+    }
+
+    // the address of element `index` of the array `array` points to
+    fn build_element(&self, array: LLVMValueRef, ty: &Type, index: u64) -> LLVMValueRef {
+        let int = unsafe { LLVMInt32TypeInContext(self.ctx) };
+        let mut indices = unsafe { [LLVMConstInt(int, 0, 0), LLVMConstInt(int, index, 0)] };
+        unsafe {
+            LLVMBuildGEP2(
+                self.builder,
+                self.llvm_type(ty),
+                array,
+                indices.as_mut_ptr(),
+                2,
+                cstr("elemtmp").as_ptr(),
+            )
+        }
+    }
+
+    // `ptr + offset` in elements of what `ptr` points to
+    fn build_offset(&self, ptr: LLVMValueRef, to: &Type, offset: LLVMValueRef) -> LLVMValueRef {
+        let mut indices = [self.convert(offset, unsafe { LLVMInt32TypeInContext(self.ctx) })];
+        unsafe {
+            LLVMBuildGEP2(
+                self.builder,
+                self.element_type(to),
+                ptr,
+                indices.as_mut_ptr(),
+                1,
+                cstr("ptrtmp").as_ptr(),
+            )
+        }
+    }
+
+    // an operator applied to values of the C types `lhs_ty` and `rhs_ty`,
+    // which sema has already checked
+    fn build_typed_binop(
+        &self,
+        op: Op,
+        (lhs, lhs_ty): (LLVMValueRef, &Type),
+        (rhs, rhs_ty): (LLVMValueRef, &Type),
+    ) -> LLVMValueRef {
+        match (op, lhs_ty.pointee(), rhs_ty.pointee()) {
+            (Op::Add, Some(to), None) => self.build_offset(lhs, to, rhs),
+            (Op::Add, None, Some(to)) => self.build_offset(rhs, to, lhs),
+            (Op::Sub, Some(to), None) => {
+                let rhs = self.to_int(rhs);
+                let neg = unsafe { LLVMBuildNeg(self.builder, rhs, cstr("negtmp").as_ptr()) };
+                self.build_offset(lhs, to, neg)
+            }
+            // the distance in elements, the addresses are a whole number of
+            // elements apart
+            (Op::Sub, Some(to), Some(_)) => unsafe {
+                let int = LLVMInt32TypeInContext(self.ctx);
+                let bytes = LLVMBuildSub(
+                    self.builder,
+                    self.convert(lhs, int),
+                    self.convert(rhs, int),
+                    cstr("subtmp").as_ptr(),
+                );
+                let size = self.convert(LLVMSizeOf(self.element_type(to)), int);
+                LLVMBuildExactSDiv(self.builder, bytes, size, cstr("difftmp").as_ptr())
+            },
+            // addresses compare unsigned, an int operand is the null pointer
+            (_, Some(_), _) => {
+                let rhs = self.convert(rhs, unsafe { LLVMTypeOf(lhs) });
+                self.build_binop(op, lhs, rhs, false)
+            }
+            (_, _, Some(_)) => {
+                let lhs = self.convert(lhs, unsafe { LLVMTypeOf(rhs) });
+                self.build_binop(op, lhs, rhs, false)
+            }
+            // a shift is done in the type of its left side, the rest in the
+            // type both sides convert to
+            _ => {
+                let ty = match op {
+                    Op::Shl | Op::Shr => lhs_ty.promoted(),
+                    _ => Type::common(lhs_ty, rhs_ty),
+                };
+                let (lhs, rhs) = (self.to_int(lhs), self.to_int(rhs));
+                self.build_binop(op, lhs, rhs, !ty.is_unsigned())
+            }
+        }
+    }
+
+    // comparisons give an i1, the rest an i32
+    fn build_binop(
+        &self,
+        op: Op,
+        lhs: LLVMValueRef,
+        rhs: LLVMValueRef,
+        signed: bool,
+    ) -> LLVMValueRef {
+        let builder = self.builder;
+        let cmp = |pred, lhs, rhs| unsafe {
+            LLVMBuildICmp(builder, pred, lhs, rhs, cstr("cmptmp").as_ptr())
+        };
+        let (le, ge, lt, gt) = if signed {
+            (LLVMIntSLE, LLVMIntSGE, LLVMIntSLT, LLVMIntSGT)
+        } else {
+            (LLVMIntULE, LLVMIntUGE, LLVMIntULT, LLVMIntUGT)
+        };
+        unsafe {
+            match op {
+                Op::Add => LLVMBuildAdd(builder, lhs, rhs, cstr("addtmp").as_ptr()),
+                Op::Sub => LLVMBuildSub(builder, lhs, rhs, cstr("subtmp").as_ptr()),
+                Op::Mul => LLVMBuildMul(builder, lhs, rhs, cstr("multmp").as_ptr()),
+                Op::Div if signed => LLVMBuildSDiv(builder, lhs, rhs, cstr("divtmp").as_ptr()),
+                Op::Div => LLVMBuildUDiv(builder, lhs, rhs, cstr("divtmp").as_ptr()),
+                // a signed remainder takes the sign of the dividend
+                Op::Rem if signed => LLVMBuildSRem(builder, lhs, rhs, cstr("remtmp").as_ptr()),
+                Op::Rem => LLVMBuildURem(builder, lhs, rhs, cstr("remtmp").as_ptr()),
+                Op::BitAnd => LLVMBuildAnd(builder, lhs, rhs, cstr("andtmp").as_ptr()),
+                Op::BitOr => LLVMBuildOr(builder, lhs, rhs, cstr("ortmp").as_ptr()),
+                Op::BitXor => LLVMBuildXor(builder, lhs, rhs, cstr("xortmp").as_ptr()),
+                Op::Shl => LLVMBuildShl(builder, lhs, rhs, cstr("shltmp").as_ptr()),
+                // an arithmetic shift keeps the sign of a signed int
+                Op::Shr if signed => LLVMBuildAShr(builder, lhs, rhs, cstr("shrtmp").as_ptr()),
+                Op::Shr => LLVMBuildLShr(builder, lhs, rhs, cstr("shrtmp").as_ptr()),
+                Op::Le => cmp(le, lhs, rhs),
+                Op::Ge => cmp(ge, lhs, rhs),
+                Op::Lt => cmp(lt, lhs, rhs),
+                Op::Gt => cmp(gt, lhs, rhs),
+                Op::Eq => cmp(LLVMIntEQ, lhs, rhs),
+                Op::Ne => cmp(LLVMIntNE, lhs, rhs),
+                Op::And | Op::Or => unreachable!("logical ops short-circuit"),
+            }
+        }
+    }
+}
+
+// statements after a `return` are unreachable and are not emitted
+fn codegen_stmts(exprs: &[Expr], llvm: &mut LLVM) -> Result<(), CodegenError> {
+    for expr in exprs {
+        if llvm.is_terminated() {
+            break;
+        }
+        expr.codegen(llvm)?;
+    }
+    Ok(())
+}
+
+fn codegen_block(exprs: &[Expr], llvm: &mut LLVM) -> Result<(), CodegenError> {
+    llvm.push_scope();
+    let result = codegen_stmts(exprs, llvm);
+    llvm.pop_scope();
+    result
+}
+
+fn codegen_loop_body(
+    body: &[Expr],
+    llvm: &mut LLVM,
+    break_bb: LLVMBasicBlockRef,
+    continue_bb: LLVMBasicBlockRef,
+) -> Result<(), CodegenError> {
+    llvm.loops.push(Loop {
+        break_bb,
+        continue_bb: Some(continue_bb),
+    });
+    let result = codegen_block(body, llvm);
+    llvm.loops.pop();
+    result
+}
+
+// a `switch` lowers to LLVM's switch instruction, which the backend may turn
+// into a jump table
+fn codegen_switch(cond: &Expr, body: &[Expr], llvm: &mut LLVM) -> Result<(), CodegenError> {
+    let val = cond.codegen_int(llvm)?;
+    // statements before the first label are never run but may declare
+    let prelude_bb =
+        unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("prelude").as_ptr()) };
+    let mut values = HashSet::new();
+    let mut cases = Vec::new();
+    let mut default_bb = None;
+    let mut label_bbs = Vec::new();
+    // sema reports bad labels, reaching one here is a bug
+    let unchecked = |span| {
+        semantic(
+            span,
+            "Internal error: switch labels were not checked".to_string(),
+        )
+    };
+    for expr in body {
+        match expr {
+            Expr::Case { value, span } => {
+                let val = match value.const_value() {
+                    Some(val) if values.insert(val) => val,
+                    _ => return unchecked(*span),
+                };
+                let bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("case").as_ptr())
+                };
+                cases.push((val, bb));
+                label_bbs.push(bb);
+            }
+            Expr::Default { span } => {
+                if default_bb.is_some() {
+                    return unchecked(*span);
+                }
+                let bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("default").as_ptr())
+                };
+                default_bb = Some(bb);
+                label_bbs.push(bb);
+            }
+            _ => {}
+        }
+    }
+    let merge_bb =
+        unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("merge").as_ptr()) };
+
+    unsafe {
+        let ty = LLVMInt32TypeInContext(llvm.ctx);
+        let default_bb = default_bb.unwrap_or(merge_bb);
+        let switch = LLVMBuildSwitch(llvm.builder, val, default_bb, cases.len() as u32);
+        for (val, bb) in cases {
+            LLVMAddCase(switch, LLVMConstInt(ty, val as u64, 1), bb);
+        }
+        LLVMPositionBuilderAtEnd(llvm.builder, prelude_bb);
+    }
+
+    let continue_bb = llvm.loops.last().and_then(|l| l.continue_bb);
+    llvm.loops.push(Loop {
+        break_bb: merge_bb,
+        continue_bb,
+    });
+    llvm.push_scope();
+    let result = codegen_switch_body(body, label_bbs, llvm);
+    llvm.pop_scope();
+    llvm.loops.pop();
+    result?;
+
+    llvm.branch_to(merge_bb);
+    unsafe { LLVMPositionBuilderAtEnd(llvm.builder, merge_bb) };
+    Ok(())
+}
+
+// each label falls through from the statement before it
+fn codegen_switch_body(
+    body: &[Expr],
+    label_bbs: Vec<LLVMBasicBlockRef>,
+    llvm: &mut LLVM,
+) -> Result<(), CodegenError> {
+    let mut label_bbs = label_bbs.into_iter();
+    for expr in body {
+        match expr {
+            Expr::Case { .. } | Expr::Default { .. } => {
+                let bb = label_bbs.next().expect("a block for every label");
+                llvm.branch_to(bb);
+                unsafe { LLVMPositionBuilderAtEnd(llvm.builder, bb) };
+            }
+            expr if !llvm.is_terminated() => {
+                expr.codegen(llvm)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// store an initializer into the storage of a `ty` at `ptr`
+fn codegen_initializer(
+    init: &Expr,
+    ptr: LLVMValueRef,
+    ty: &Type,
+    llvm: &mut LLVM,
+) -> Result<(), CodegenError> {
+    match (ty.unqualified(), init) {
+        (Type::Array(elem, len), Expr::InitList { values, .. })
+            if values.len() <= *len as usize =>
+        {
+            for (i, value) in values.iter().enumerate() {
+                let elem_ptr = llvm.build_element(ptr, ty, i as u64);
+                codegen_initializer(value, elem_ptr, elem, llvm)?;
+            }
+            Ok(())
+        }
+        (Type::Array(..), _) | (_, Expr::InitList { .. }) => semantic(
+            init.span(),
+            format!("Invalid initializer `{}`", init.deparse()),
+        ),
+        _ => {
+            let val = init.codegen_value(llvm)?;
+            llvm.build_store(val, ptr, ty);
+            Ok(())
+        }
+    }
+}
+
+fn codegen_for(
+    init: &Option<Box<Expr>>,
+    cond: &Option<Box<Expr>>,
+    step: &Option<Box<Expr>>,
+    body: &[Expr],
+    llvm: &mut LLVM,
+) -> Result<(), CodegenError> {
+    let cond_bb =
+        unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("cond").as_ptr()) };
+    let body_bb =
+        unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("body").as_ptr()) };
+    let step_bb =
+        unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("step").as_ptr()) };
+    let merge_bb =
+        unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("merge").as_ptr()) };
+
+    if let Some(init) = init {
+        init.codegen(llvm)?;
+    }
+    unsafe {
+        LLVMBuildBr(llvm.builder, cond_bb);
+        LLVMPositionBuilderAtEnd(llvm.builder, cond_bb);
+    }
+
+    match cond {
+        Some(cond) => {
+            let cond_val = cond.codegen_cond(llvm)?;
+            unsafe { LLVMBuildCondBr(llvm.builder, cond_val, body_bb, merge_bb) };
+        }
+        None => unsafe {
+            LLVMBuildBr(llvm.builder, body_bb);
+        },
+    }
+    unsafe { LLVMPositionBuilderAtEnd(llvm.builder, body_bb) };
+
+    codegen_loop_body(body, llvm, merge_bb, step_bb)?;
+    llvm.branch_to(step_bb);
+    unsafe { LLVMPositionBuilderAtEnd(llvm.builder, step_bb) };
+    if let Some(step) = step {
+        step.codegen(llvm)?;
+    }
+    unsafe {
+        LLVMBuildBr(llvm.builder, cond_bb);
+        LLVMPositionBuilderAtEnd(llvm.builder, merge_bb);
+    }
+    Ok(())
 }
 
 impl Expr {
-    fn codegen(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, String> {
+    // C has no boolean type: comparisons yield an i1 which is widened to int
+    // wherever a value is needed, and values are compared against zero
+    // wherever a condition is needed
+    fn codegen_value(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
+        let val = self.codegen(llvm)?;
+        if unsafe { LLVMGetTypeKind(LLVMTypeOf(val)) } == LLVMTypeKind::LLVMVoidTypeKind {
+            return semantic(
+                self.span(),
+                format!("Void value of `{}` used", self.deparse()),
+            );
+        }
+        Ok(val)
+    }
+
+    fn codegen_int(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
+        let val = self.codegen_value(llvm)?;
+        Ok(llvm.convert(val, unsafe { LLVMInt32TypeInContext(llvm.ctx) }))
+    }
+
+    // the type of the storage an lvalue names, arrays are still arrays
+    fn lvalue_type(&self, llvm: &LLVM) -> Type {
+        match self {
+            Expr::Var { name, span } => llvm
+                .lookup(name, *span)
+                .map_or(Type::Int, |scoped| scoped.ty.clone()),
+            Expr::Deref { addr, .. } => match addr.c_type(llvm).pointee() {
+                Some(to) => to.clone(),
+                None => Type::Volatile(Box::new(Type::Int)),
+            },
+            Expr::Index { base, .. } => base.c_type(llvm).pointee().cloned().unwrap_or(Type::Int),
+            expr => expr.c_type(llvm),
+        }
+    }
+
+    // the C type of the value, as sema found it
+    fn c_type(&self, llvm: &LLVM) -> Type {
+        let pointer = |ty: &Type| ty.pointee().map(|_| ty.unqualified().clone());
+        match self {
+            Expr::Var { .. } | Expr::Deref { .. } | Expr::Index { .. } => {
+                self.lvalue_type(llvm).decay()
+            }
+            Expr::Unary {
+                op: UnaryOp::AddrOf,
+                expr,
+                ..
+            } => Type::Pointer(Box::new(expr.lvalue_type(llvm))),
+            Expr::BinOp { lhs, rhs, op, .. } => {
+                let (lhs, rhs) = (lhs.c_type(llvm), rhs.c_type(llvm));
+                match op {
+                    // the distance between two pointers
+                    Op::Sub if rhs.pointee().is_some() => None,
+                    Op::Add => pointer(&lhs).or(pointer(&rhs)),
+                    Op::Sub => pointer(&lhs),
+                    _ => None,
+                }
+                .unwrap_or_else(|| Type::arithmetic(*op, &lhs, &rhs))
+            }
+            Expr::Unary {
+                op: UnaryOp::Not, ..
+            } => Type::Int,
+            Expr::Unary { expr, .. } => expr.c_type(llvm).promoted(),
+            Expr::Assign { lhs, .. } | Expr::CompoundAssign { lhs, .. } => {
+                lhs.c_type(llvm).unqualified().clone()
+            }
+            Expr::IncDec { expr, .. } => expr.c_type(llvm).unqualified().clone(),
+            Expr::Conditional {
+                then, otherwise, ..
+            } => {
+                let (then, otherwise) = (then.c_type(llvm), otherwise.c_type(llvm));
+                pointer(&then)
+                    .or(pointer(&otherwise))
+                    .unwrap_or_else(|| Type::common(&then, &otherwise))
+            }
+            Expr::Call { name, .. } => llvm.functions.get(name).cloned().unwrap_or(Type::Int),
+            Expr::Int { value, .. } => Type::literal(*value),
+            _ => Type::Void,
+        }
+    }
+
+    fn codegen_cond(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
+        let val = self.codegen_value(llvm)?;
+        Ok(llvm.to_bool(val))
+    }
+
+    // the address of the storage an expression names, and the type stored
+    fn codegen_lvalue(&self, llvm: &mut LLVM) -> Result<(LLVMValueRef, Type), CodegenError> {
+        match self {
+            Expr::Var { name, span } => {
+                let scoped = llvm.lookup(name, *span)?;
+                Ok((scoped.val, scoped.ty.clone()))
+            }
+            Expr::Deref { addr, .. } => {
+                let ty = self.lvalue_type(llvm);
+                if addr.c_type(llvm).pointee().is_some() {
+                    return Ok((addr.codegen_value(llvm)?, ty));
+                }
+                // an int is taken as the address of an int
+                let addr_val = addr.codegen_int(llvm)?;
+                let ptr = unsafe {
+                    LLVMBuildIntToPtr(
+                        llvm.builder,
+                        addr_val,
+                        LLVMPointerType(LLVMInt32TypeInContext(llvm.ctx), 0),
+                        cstr("deref").as_ptr(),
+                    )
+                };
+                Ok((ptr, ty))
+            }
+            Expr::Index { base, index, .. } => {
+                let elem = match base.c_type(llvm).pointee() {
+                    Some(elem) => elem.clone(),
+                    None => {
+                        return semantic(
+                            base.span(),
+                            format!("`{}` is not an array or pointer", base.deparse()),
+                        )
+                    }
+                };
+                let base_val = base.codegen_value(llvm)?;
+                let index_val = index.codegen_value(llvm)?;
+                Ok((llvm.build_offset(base_val, &elem, index_val), elem))
+            }
+            _ => semantic(
+                self.span(),
+                format!("`{}` is not an lvalue", self.deparse()),
+            ),
+        }
+    }
+
+    fn codegen(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
         match self {
-            Expr::Int { value } => {
+            Expr::ExprStmt { expr, .. } => {
+                expr.codegen(llvm)?;
+                Ok(std::ptr::null_mut())
+            }
+            Expr::Int { value, .. } => {
                 let ty = unsafe { LLVMInt32TypeInContext(llvm.ctx) };
                 let val = unsafe { LLVMConstInt(ty, *value as u64, 0) };
                 Ok(val)
             }
-            Expr::BinOp { lhs, rhs, op } => {
-                let lhsval = lhs.codegen(llvm)?;
-                let rhsval = rhs.codegen(llvm)?;
-                match op {
-                    Op::Add => {
-                        let val = unsafe {
-                            LLVMBuildAdd(llvm.builder, lhsval, rhsval, cstr("addtmp").as_ptr())
-                        };
-                        Ok(val)
-                    }
-                    Op::Sub => {
-                        let val = unsafe {
-                            LLVMBuildSub(llvm.builder, lhsval, rhsval, cstr("subtmp").as_ptr())
-                        };
-                        Ok(val)
-                    }
-                    Op::Mul => {
-                        let val = unsafe {
-                            LLVMBuildMul(llvm.builder, lhsval, rhsval, cstr("multmp").as_ptr())
-                        };
-                        Ok(val)
-                    }
-                    Op::Div => {
-                        let val = unsafe {
-                            LLVMBuildSDiv(llvm.builder, lhsval, rhsval, cstr("divtmp").as_ptr())
-                        };
-                        Ok(val)
+            Expr::BinOp {
+                lhs,
+                rhs,
+                op: op @ (Op::And | Op::Or),
+                ..
+            } => {
+                // the right side only runs when the left side does not
+                // already decide the result
+                let lhsval = lhs.codegen_cond(llvm)?;
+                let lhs_bb = unsafe { LLVMGetInsertBlock(llvm.builder) };
+                let rhs_bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("rhs").as_ptr())
+                };
+                let merge_bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("logicmerge").as_ptr())
+                };
+                let short_circuit = match op {
+                    Op::And => {
+                        unsafe { LLVMBuildCondBr(llvm.builder, lhsval, rhs_bb, merge_bb) };
+                        0
                     }
-                    Op::Le => {
-                        let val = unsafe {
-                            LLVMBuildICmp(
-                                llvm.builder,
-                                LLVMIntSLE,
-                                lhsval,
-                                rhsval,
-                                cstr("cmptmp").as_ptr(),
-                            )
-                        };
-                        Ok(val)
+                    _ => {
+                        unsafe { LLVMBuildCondBr(llvm.builder, lhsval, merge_bb, rhs_bb) };
+                        1
                     }
-                    Op::Ge => {
-                        let val = unsafe {
-                            LLVMBuildICmp(
-                                llvm.builder,
-                                LLVMIntSGE,
-                                lhsval,
-                                rhsval,
-                                cstr("cmptmp").as_ptr(),
-                            )
-                        };
-                        Ok(val)
+                };
+
+                unsafe { LLVMPositionBuilderAtEnd(llvm.builder, rhs_bb) };
+                let rhsval = rhs.codegen_cond(llvm)?;
+                let rhs_end_bb = unsafe { LLVMGetInsertBlock(llvm.builder) };
+                unsafe {
+                    LLVMBuildBr(llvm.builder, merge_bb);
+                    LLVMPositionBuilderAtEnd(llvm.builder, merge_bb);
+                }
+
+                let bool_ty = unsafe { LLVMInt1TypeInContext(llvm.ctx) };
+                let phi = unsafe { LLVMBuildPhi(llvm.builder, bool_ty, cstr("logictmp").as_ptr()) };
+                let mut values = [unsafe { LLVMConstInt(bool_ty, short_circuit, 0) }, rhsval];
+                let mut blocks = [lhs_bb, rhs_end_bb];
+                unsafe { LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2) };
+                Ok(phi)
+            }
+            Expr::BinOp { lhs, rhs, op, .. } => {
+                let lhsval = lhs.codegen_value(llvm)?;
+                let rhsval = rhs.codegen_value(llvm)?;
+                Ok(llvm.build_typed_binop(
+                    *op,
+                    (lhsval, &lhs.c_type(llvm)),
+                    (rhsval, &rhs.c_type(llvm)),
+                ))
+            }
+            Expr::Unary { op, expr, .. } => {
+                let val = match op {
+                    UnaryOp::Neg => {
+                        let val = expr.codegen_int(llvm)?;
+                        unsafe { LLVMBuildNeg(llvm.builder, val, cstr("negtmp").as_ptr()) }
                     }
-                    Op::Lt => {
-                        let val = unsafe {
-                            LLVMBuildICmp(
-                                llvm.builder,
-                                LLVMIntSLT,
-                                lhsval,
-                                rhsval,
-                                cstr("cmptmp").as_ptr(),
-                            )
-                        };
-                        Ok(val)
+                    // yields an i1 like the comparisons
+                    UnaryOp::Not => {
+                        let val = expr.codegen_cond(llvm)?;
+                        unsafe { LLVMBuildNot(llvm.builder, val, cstr("nottmp").as_ptr()) }
                     }
-                    Op::Gt => {
-                        let val = unsafe {
-                            LLVMBuildICmp(
-                                llvm.builder,
-                                LLVMIntSGT,
-                                lhsval,
-                                rhsval,
-                                cstr("cmptmp").as_ptr(),
-                            )
-                        };
-                        Ok(val)
+                    UnaryOp::BitNot => {
+                        let val = expr.codegen_int(llvm)?;
+                        unsafe { LLVMBuildNot(llvm.builder, val, cstr("nottmp").as_ptr()) }
                     }
-                    Op::Eq => {
-                        let val = unsafe {
-                            LLVMBuildICmp(
-                                llvm.builder,
-                                LLVMIntEQ,
-                                lhsval,
-                                rhsval,
-                                cstr("cmptmp").as_ptr(),
-                            )
-                        };
-                        Ok(val)
+                    UnaryOp::AddrOf => expr.codegen_lvalue(llvm)?.0,
+                };
+                Ok(val)
+            }
+            Expr::Return { expr, span } => {
+                if let Some(expr) = expr {
+                    if llvm.ret_val.is_null() {
+                        return semantic(*span, "Void function returns a value".to_string());
                     }
-                    Op::Ne => {
-                        let val = unsafe {
-                            LLVMBuildICmp(
-                                llvm.builder,
-                                LLVMIntNE,
-                                lhsval,
-                                rhsval,
-                                cstr("cmptmp").as_ptr(),
-                            )
-                        };
-                        Ok(val)
+                    let val = expr.codegen_value(llvm)?;
+                    let ty = unsafe { LLVMGetAllocatedType(llvm.ret_val) };
+                    unsafe { LLVMBuildStore(llvm.builder, llvm.convert(val, ty), llvm.ret_val) };
+                }
+                unsafe { LLVMBuildBr(llvm.builder, llvm.ret_block) };
+                Ok(std::ptr::null_mut())
+            }
+            // a constant condition selects its arm without branching
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => match cond.const_value() {
+                Some(0) => {
+                    let val = otherwise.codegen_value(llvm)?;
+                    Ok(llvm.convert(val, llvm.value_type(&self.c_type(llvm))))
+                }
+                Some(_) => {
+                    let val = then.codegen_value(llvm)?;
+                    Ok(llvm.convert(val, llvm.value_type(&self.c_type(llvm))))
+                }
+                None => {
+                    let ty = llvm.value_type(&self.c_type(llvm));
+                    let then_bb = unsafe {
+                        LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("then").as_ptr())
+                    };
+                    let else_bb = unsafe {
+                        LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("else").as_ptr())
+                    };
+                    let merge_bb = unsafe {
+                        LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("merge").as_ptr())
+                    };
+
+                    let cond_val = cond.codegen_cond(llvm)?;
+                    unsafe {
+                        LLVMBuildCondBr(llvm.builder, cond_val, then_bb, else_bb);
+                        LLVMPositionBuilderAtEnd(llvm.builder, then_bb);
                     }
-                    Op::And => {
-                        let val = unsafe {
-                            LLVMBuildAnd(llvm.builder, lhsval, rhsval, cstr("andtmp").as_ptr())
-                        };
-                        Ok(val)
+
+                    // an arm may end in another block than it started in
+                    let then_val = then.codegen_value(llvm)?;
+                    let then_val = llvm.convert(then_val, ty);
+                    let then_end_bb = unsafe { LLVMGetInsertBlock(llvm.builder) };
+                    unsafe {
+                        LLVMBuildBr(llvm.builder, merge_bb);
+                        LLVMPositionBuilderAtEnd(llvm.builder, else_bb);
                     }
-                    Op::Or => {
-                        let val = unsafe {
-                            LLVMBuildOr(llvm.builder, lhsval, rhsval, cstr("ortmp").as_ptr())
-                        };
-                        Ok(val)
+
+                    let else_val = otherwise.codegen_value(llvm)?;
+                    let else_val = llvm.convert(else_val, ty);
+                    let else_end_bb = unsafe { LLVMGetInsertBlock(llvm.builder) };
+                    unsafe {
+                        LLVMBuildBr(llvm.builder, merge_bb);
+                        LLVMPositionBuilderAtEnd(llvm.builder, merge_bb);
                     }
+
+                    let phi = unsafe { LLVMBuildPhi(llvm.builder, ty, cstr("condtmp").as_ptr()) };
+                    let mut values = [then_val, else_val];
+                    let mut blocks = [then_end_bb, else_end_bb];
+                    unsafe { LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2) };
+                    Ok(phi)
                 }
-            }
-            Expr::Return { expr } => {
-                unsafe { LLVMBuildStore(llvm.builder, expr.codegen(llvm)?, llvm.ret_val) };
-                Ok(std::ptr::null_mut())
-            }
+            },
             Expr::If {
                 cond,
                 then,
                 otherwise,
+                ..
             } => {
                 let then_bb = unsafe {
                     LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("then").as_ptr())
@@ -195,116 +981,88 @@ impl Expr {
                     LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("merge").as_ptr())
                 };
 
-                let cond_val = cond.codegen(llvm)?;
+                let cond_val = cond.codegen_cond(llvm)?;
                 unsafe {
                     LLVMBuildCondBr(llvm.builder, cond_val, then_bb, else_bb);
                     LLVMPositionBuilderAtEnd(llvm.builder, then_bb);
                 }
 
-                let mut is_ret = false;
-                for expr in then {
-                    expr.codegen(llvm)?;
-                    match expr {
-                        Expr::Return { .. } => {
-                            is_ret = true;
-                            break;
-                        }
-                        _ => (),
-                    }
-                }
-                let br_block = if is_ret { llvm.ret_block } else { merge_bb };
-                unsafe {
-                    LLVMBuildBr(llvm.builder, br_block);
-                    LLVMPositionBuilderAtEnd(llvm.builder, else_bb);
-                }
+                codegen_block(then, llvm)?;
+                llvm.branch_to(merge_bb);
+                unsafe { LLVMPositionBuilderAtEnd(llvm.builder, else_bb) };
 
-                let mut is_ret = false;
-                for expr in otherwise {
-                    expr.codegen(llvm)?;
-                    match expr {
-                        Expr::Return { .. } => {
-                            is_ret = true;
-                            break;
-                        }
-                        _ => (),
-                    }
-                }
-                let br_block = if is_ret { llvm.ret_block } else { merge_bb };
-                unsafe {
-                    LLVMBuildBr(llvm.builder, br_block);
-                    LLVMPositionBuilderAtEnd(llvm.builder, merge_bb);
+                codegen_block(otherwise, llvm)?;
+                llvm.branch_to(merge_bb);
+
+                // when both branches leave the function nothing reaches the
+                // merge block, so drop it and stay in the terminated block
+                if unsafe { LLVMGetFirstUse(LLVMBasicBlockAsValue(merge_bb)) }.is_null() {
+                    unsafe { LLVMDeleteBasicBlock(merge_bb) };
+                } else {
+                    unsafe { LLVMPositionBuilderAtEnd(llvm.builder, merge_bb) };
                 }
 
                 Ok(std::ptr::null_mut())
             }
-            Expr::Decl { ty, name, init } => {
-                let ty = match ty {
-                    Type::Int => unsafe { LLVMInt32TypeInContext(llvm.ctx) },
-                    Type::Void => unsafe { LLVMVoidTypeInContext(llvm.ctx) },
-                };
-                let val = unsafe { LLVMBuildAlloca(llvm.builder, ty, cstr(name).as_ptr()) };
-                llvm.locals.insert(name.clone(), Scoped { val, ty });
-                match init {
-                    Some(init) => {
-                        let init_val = init.codegen(llvm)?;
-                        unsafe { LLVMBuildStore(llvm.builder, init_val, val) };
+            Expr::Decl { ty, name, init, .. } => {
+                let llvm_ty = llvm.llvm_type(ty);
+                let val = llvm.alloca(llvm_ty, name);
+                llvm.declare(
+                    name,
+                    Scoped {
+                        val,
+                        ty: ty.clone(),
+                    },
+                );
+                if let Some(init) = init {
+                    // elements without an initializer are zero
+                    if let Expr::InitList { .. } = **init {
+                        unsafe { LLVMBuildStore(llvm.builder, LLVMConstNull(llvm_ty), val) };
                     }
-                    None => {}
+                    codegen_initializer(init, val, ty, llvm)?;
                 }
                 Ok(val)
             }
-            Expr::Var { name } => {
-                let scoped = llvm.locals.get(name).unwrap();
-                let val = unsafe {
-                    LLVMBuildLoad2(llvm.builder, scoped.ty, scoped.val, cstr(name).as_ptr())
-                };
-                Ok(val)
+            Expr::Var { name, span } => {
+                let scoped = llvm.lookup(name, *span)?;
+                Ok(llvm.build_load(scoped.val, &scoped.ty, name))
             }
-            Expr::Assign { lhs, rhs } => {
-                let ptr = match &**lhs {
-                    Expr::Var { name } => {
-                        let scoped = llvm.locals.get(name).unwrap();
-                        scoped.val
-                    }
-                    Expr::Deref { addr } => {
-                        let addr_val = addr.codegen(llvm)?;
-                        unsafe {
-                            LLVMBuildIntToPtr(
-                                llvm.builder,
-                                addr_val,
-                                LLVMPointerType(LLVMInt32TypeInContext(llvm.ctx), 0),
-                                cstr("deref").as_ptr(),
-                            )
-                        }
-                    }
-                    _ => panic!("invalid lhs"),
-                };
-                let val = rhs.codegen(llvm)?;
-                let store = unsafe { LLVMBuildStore(llvm.builder, val, ptr) };
-                unsafe { LLVMSetVolatile(store, 1) };
-                Ok(val)
+            Expr::Assign { lhs, rhs, .. } => {
+                let (ptr, ty) = lhs.codegen_lvalue(llvm)?;
+                let val = rhs.codegen_value(llvm)?;
+                Ok(llvm.build_store(val, ptr, &ty))
             }
-            Expr::Deref { addr } => {
-                let addr_val = addr.codegen(llvm)?;
-                let ptr_val = unsafe {
-                    LLVMBuildIntToPtr(
-                        llvm.builder,
-                        addr_val,
-                        LLVMPointerType(LLVMInt32TypeInContext(llvm.ctx), 0),
-                        cstr("deref").as_ptr(),
-                    )
-                };
-                let val = unsafe {
-                    LLVMBuildLoad2(
-                        llvm.builder,
-                        LLVMInt32TypeInContext(llvm.ctx),
-                        ptr_val,
-                        cstr("deref").as_ptr(),
-                    )
-                };
-                Ok(val)
+            // the lvalue is evaluated once, `*addr += 1` touches one address
+            Expr::CompoundAssign { lhs, rhs, op, .. } => {
+                let (ptr, ty) = lhs.codegen_lvalue(llvm)?;
+                let old = llvm.build_load(ptr, &ty, "oldtmp");
+                let val = rhs.codegen_value(llvm)?;
+                let new = llvm.build_typed_binop(*op, (old, &ty), (val, &rhs.c_type(llvm)));
+                Ok(llvm.build_store(new, ptr, &ty))
+            }
+            Expr::IncDec {
+                op, prefix, expr, ..
+            } => {
+                let (ptr, ty) = expr.codegen_lvalue(llvm)?;
+                let old = llvm.build_load(ptr, &ty, "oldtmp");
+                let one = unsafe { LLVMConstInt(LLVMInt32TypeInContext(llvm.ctx), 1, 0) };
+                let new = llvm.build_typed_binop(*op, (old, &ty), (one, &Type::Int));
+                let new = llvm.build_store(new, ptr, &ty);
+                Ok(if *prefix { new } else { old })
+            }
+            Expr::Deref { .. } => {
+                let (ptr, ty) = self.codegen_lvalue(llvm)?;
+                Ok(llvm.build_load(ptr, &ty, "deref"))
+            }
+            Expr::Index { .. } => {
+                let (ptr, ty) = self.codegen_lvalue(llvm)?;
+                Ok(llvm.build_load(ptr, &ty, "index"))
             }
-            Expr::While { cond, body } => {
+            Expr::InitList { span, .. } => semantic(
+                *span,
+                "Initializer list outside of a declaration".to_string(),
+            ),
+            Expr::While { cond, body, .. } => {
                 let cond_bb = unsafe {
                     LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("cond").as_ptr())
                 };
@@ -320,146 +1078,1103 @@ impl Expr {
                     LLVMPositionBuilderAtEnd(llvm.builder, cond_bb);
                 }
 
-                let cond_val = cond.codegen(llvm)?;
+                let cond_val = cond.codegen_cond(llvm)?;
                 unsafe {
                     LLVMBuildCondBr(llvm.builder, cond_val, body_bb, merge_bb);
                     LLVMPositionBuilderAtEnd(llvm.builder, body_bb);
                 }
 
-                let mut is_ret = false;
-                for expr in body {
-                    expr.codegen(llvm)?;
-                    match expr {
-                        Expr::Return { .. } => {
-                            is_ret = true;
-                            break;
-                        }
-                        _ => (),
-                    }
+                codegen_loop_body(body, llvm, merge_bb, cond_bb)?;
+                llvm.branch_to(cond_bb);
+                unsafe { LLVMPositionBuilderAtEnd(llvm.builder, merge_bb) };
+
+                Ok(std::ptr::null_mut())
+            }
+            Expr::For {
+                init,
+                cond,
+                step,
+                body,
+                ..
+            } => {
+                // a declaration in `init` is scoped to the loop
+                llvm.push_scope();
+                let result = codegen_for(init, cond, step, body, llvm);
+                llvm.pop_scope();
+                result?;
+
+                Ok(std::ptr::null_mut())
+            }
+            Expr::DoWhile { body, cond, .. } => {
+                let body_bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("body").as_ptr())
+                };
+                let cond_bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("cond").as_ptr())
+                };
+                let merge_bb = unsafe {
+                    LLVMAppendBasicBlockInContext(llvm.ctx, llvm.func, cstr("merge").as_ptr())
+                };
+
+                unsafe {
+                    LLVMBuildBr(llvm.builder, body_bb);
+                    LLVMPositionBuilderAtEnd(llvm.builder, body_bb);
                 }
-                let br_block = if is_ret { llvm.ret_block } else { cond_bb };
+
+                codegen_loop_body(body, llvm, merge_bb, cond_bb)?;
+                llvm.branch_to(cond_bb);
+                unsafe { LLVMPositionBuilderAtEnd(llvm.builder, cond_bb) };
+                let cond_val = cond.codegen_cond(llvm)?;
                 unsafe {
-                    LLVMBuildBr(llvm.builder, br_block);
+                    LLVMBuildCondBr(llvm.builder, cond_val, body_bb, merge_bb);
                     LLVMPositionBuilderAtEnd(llvm.builder, merge_bb);
                 }
 
                 Ok(std::ptr::null_mut())
             }
+            Expr::Break { span } | Expr::Continue { span } => {
+                let innermost = llvm.loops.last();
+                let target = match self {
+                    Expr::Break { .. } => innermost.map(|l| l.break_bb).ok_or("a loop or switch"),
+                    _ => innermost.and_then(|l| l.continue_bb).ok_or("a loop"),
+                };
+                match target {
+                    Ok(bb) => unsafe { LLVMBuildBr(llvm.builder, bb) },
+                    Err(outside) => {
+                        return semantic(
+                            *span,
+                            format!("`{}` outside of {}", self.deparse(), outside),
+                        )
+                    }
+                };
+                Ok(std::ptr::null_mut())
+            }
+            Expr::Switch { cond, body, .. } => {
+                codegen_switch(cond, body, llvm)?;
+                Ok(std::ptr::null_mut())
+            }
+            Expr::Case { span, .. } | Expr::Default { span } => semantic(
+                *span,
+                format!("`{}` label not directly in a switch", self.deparse()),
+            ),
+            Expr::Call { name, args, span } => {
+                let func = unsafe { LLVMGetNamedFunction(llvm.module, cstr(name).as_ptr()) };
+                if func.is_null() {
+                    return semantic(*span, format!("Unknown function `{}`", name));
+                }
+                let param_count = unsafe { LLVMCountParams(func) } as usize;
+                if param_count != args.len() {
+                    return semantic(
+                        *span,
+                        format!(
+                            "Function `{}` takes {} arguments but {} were given",
+                            name,
+                            param_count,
+                            args.len()
+                        ),
+                    );
+                }
+                let fn_type = unsafe { LLVMGlobalGetValueType(func) };
+                let mut param_types = vec![std::ptr::null_mut(); param_count];
+                unsafe { LLVMGetParamTypes(fn_type, param_types.as_mut_ptr()) };
+                let mut arg_vals = Vec::new();
+                for (arg, ty) in args.iter().zip(param_types) {
+                    let val = arg.codegen_value(llvm)?;
+                    arg_vals.push(llvm.convert(val, ty));
+                }
+                let returns_void = unsafe {
+                    LLVMGetTypeKind(LLVMGetReturnType(fn_type)) == LLVMTypeKind::LLVMVoidTypeKind
+                };
+                // void results cannot be named
+                let name = if returns_void { "" } else { "calltmp" };
+                let val = unsafe {
+                    LLVMBuildCall2(
+                        llvm.builder,
+                        fn_type,
+                        func,
+                        arg_vals.as_mut_ptr(),
+                        arg_vals.len() as u32,
+                        cstr(name).as_ptr(),
+                    )
+                };
+                if returns_void {
+                    return Ok(val);
+                }
+                Ok(llvm.widen(val, &self.c_type(llvm)))
+            }
         }
     }
 }
 
 impl Function {
-    fn codegen(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, String> {
-        let ret_type = unsafe { LLVMInt32TypeInContext(llvm.ctx) };
-
-        let mut arg_types = Vec::new();
-        for arg in &self.args {
-            let ty = match arg.ty {
-                Type::Int => unsafe { LLVMInt32TypeInContext(llvm.ctx) },
-                Type::Void => unsafe { LLVMVoidTypeInContext(llvm.ctx) },
-            };
-            arg_types.push(ty);
-        }
+    fn declare(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
+        let ret_type = llvm.llvm_type(&self.ret_type);
+        let mut arg_types = self
+            .args
+            .iter()
+            .map(|arg| llvm.llvm_type(&arg.ty))
+            .collect::<Vec<_>>();
 
         let fn_type = unsafe {
             LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as u32, 0)
         };
         let name = cstr(&self.name);
+        llvm.functions
+            .insert(self.name.clone(), self.ret_type.clone());
+        let existing = unsafe { LLVMGetNamedFunction(llvm.module, name.as_ptr()) };
+        if !existing.is_null() {
+            if unsafe { LLVMGlobalGetValueType(existing) } != fn_type {
+                return semantic(
+                    self.span,
+                    format!("Conflicting types for function `{}`", self.name),
+                );
+            }
+            return Ok(existing);
+        }
         let fn_value = unsafe { LLVMAddFunction(llvm.module, name.as_ptr(), fn_type) };
+        if self.is_static {
+            unsafe { LLVMSetLinkage(fn_value, LLVMLinkage::LLVMInternalLinkage) };
+        }
+        Ok(fn_value)
+    }
+
+    fn codegen(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
+        let fn_value = self.declare(llvm)?;
+        if unsafe { LLVMCountBasicBlocks(fn_value) } > 0 {
+            return semantic(
+                self.span,
+                format!("Redefinition of function `{}`", self.name),
+            );
+        }
         llvm.func = fn_value;
 
+        let fn_type = unsafe { LLVMGlobalGetValueType(fn_value) };
+        let ret_type = unsafe { LLVMGetReturnType(fn_type) };
+        let mut arg_types = vec![std::ptr::null_mut(); self.args.len()];
+        unsafe { LLVMGetParamTypes(fn_type, arg_types.as_mut_ptr()) };
+
         let bb =
             unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, fn_value, cstr("entry").as_ptr()) };
         unsafe { LLVMPositionBuilderAtEnd(llvm.builder, bb) };
-        llvm.ret_val = unsafe { LLVMBuildAlloca(llvm.builder, ret_type, cstr("ret").as_ptr()) };
-        unsafe { LLVMBuildStore(llvm.builder, LLVMConstInt(ret_type, 0, 0), llvm.ret_val) };
+        let is_void = self.ret_type == Type::Void;
+        llvm.ret_val = std::ptr::null_mut();
+        if !is_void {
+            llvm.ret_val = unsafe { LLVMBuildAlloca(llvm.builder, ret_type, cstr("ret").as_ptr()) };
+            unsafe { LLVMBuildStore(llvm.builder, LLVMConstNull(ret_type), llvm.ret_val) };
+        }
 
         llvm.ret_block =
             unsafe { LLVMAppendBasicBlockInContext(llvm.ctx, fn_value, cstr("retblock").as_ptr()) };
         unsafe { LLVMPositionBuilderAtEnd(llvm.builder, bb) };
 
+        llvm.push_scope();
         for (i, arg) in self.args.iter().enumerate() {
             let name = cstr(&arg.name);
-            let val = unsafe { LLVMGetParam(fn_value, i as u32) };
-            unsafe { LLVMSetValueName2(val, name.as_ptr(), arg.name.len()) };
-            llvm.locals.insert(
-                arg.name.clone(),
+            let param = unsafe { LLVMGetParam(fn_value, i as u32) };
+            unsafe { LLVMSetValueName2(param, name.as_ptr(), arg.name.len()) };
+            let val = unsafe { LLVMBuildAlloca(llvm.builder, arg_types[i], name.as_ptr()) };
+            unsafe { LLVMBuildStore(llvm.builder, param, val) };
+            llvm.declare(
+                &arg.name,
                 Scoped {
                     val,
-                    ty: arg_types[i],
+                    ty: arg.ty.clone(),
                 },
             );
         }
 
-        for expr in &self.exprs {
-            expr.codegen(llvm)?;
-        }
-        unsafe { LLVMBuildBr(llvm.builder, llvm.ret_block) };
+        codegen_stmts(&self.exprs, llvm)?;
+        llvm.pop_scope();
+        llvm.branch_to(llvm.ret_block);
         unsafe { LLVMPositionBuilderAtEnd(llvm.builder, llvm.ret_block) };
-        Ok(unsafe { LLVMBuildRet(llvm.builder, llvm.ret_val) })
+        if is_void {
+            return Ok(unsafe { LLVMBuildRetVoid(llvm.builder) });
+        }
+        let ret_val = unsafe {
+            LLVMBuildLoad2(
+                llvm.builder,
+                ret_type,
+                llvm.ret_val,
+                cstr("retval").as_ptr(),
+            )
+        };
+        Ok(unsafe { LLVMBuildRet(llvm.builder, ret_val) })
+    }
+}
+
+// the address of a global, or of an element of a global array at a
+// constant index, with the type of the object there
+fn const_object(expr: &Expr, llvm: &LLVM) -> Option<(LLVMValueRef, Type)> {
+    match expr {
+        Expr::Var { name, span } => {
+            let global = llvm.lookup(name, *span).ok()?;
+            Some((global.val, global.ty.clone()))
+        }
+        Expr::Index { base, index, .. } => {
+            let (array, ty) = const_object(base, llvm)?;
+            let (Type::Array(elem, _), Some(i)) = (ty.unqualified(), index.const_value()) else {
+                return None;
+            };
+            let ptr = const_element(array, &ty, i, llvm);
+            Some((ptr, (**elem).clone()))
+        }
+        _ => None,
+    }
+}
+
+// the constant address of element `index` of the array `array` points to
+fn const_element(array: LLVMValueRef, ty: &Type, index: i32, llvm: &LLVM) -> LLVMValueRef {
+    let int = unsafe { LLVMInt32TypeInContext(llvm.ctx) };
+    let mut indices = unsafe { [LLVMConstInt(int, 0, 0), LLVMConstInt(int, index as u64, 1)] };
+    unsafe { LLVMConstInBoundsGEP2(llvm.llvm_type(ty), array, indices.as_mut_ptr(), 2) }
+}
+
+// an address the linker fills in, as in `&x`, `&a[1]` or an array `a`
+fn const_address(expr: &Expr, llvm: &LLVM) -> Option<LLVMValueRef> {
+    match expr {
+        Expr::Unary {
+            op: UnaryOp::AddrOf,
+            expr,
+            ..
+        } => const_object(expr, llvm).map(|(ptr, _)| ptr),
+        expr => match const_object(expr, llvm)? {
+            (ptr, ty) if matches!(ty.unqualified(), Type::Array(..)) => {
+                Some(const_element(ptr, &ty, 0, llvm))
+            }
+            _ => None,
+        },
+    }
+}
+
+// the constant a global of type `ty` starts out as
+fn const_initializer(
+    init: &Expr,
+    ty: &Type,
+    name: &str,
+    llvm: &LLVM,
+) -> Result<LLVMValueRef, CodegenError> {
+    match (ty.unqualified(), init) {
+        (Type::Array(elem, len), Expr::InitList { values, .. })
+            if values.len() <= *len as usize =>
+        {
+            // elements without an initializer are zero
+            let mut elems = vec![unsafe { LLVMConstNull(llvm.llvm_type(elem)) }; *len as usize];
+            for (i, value) in values.iter().enumerate() {
+                elems[i] = const_initializer(value, elem, name, llvm)?;
+            }
+            Ok(unsafe { LLVMConstArray(llvm.llvm_type(elem), elems.as_mut_ptr(), *len) })
+        }
+        (Type::Array(..), _) | (_, Expr::InitList { .. }) => semantic(
+            init.span(),
+            format!("Invalid initializer `{}`", init.deparse()),
+        ),
+        (ty, init) => {
+            let llvm_ty = llvm.llvm_type(ty);
+            if let Some(addr) = const_address(init, llvm) {
+                return Ok(match ty.pointee() {
+                    Some(_) => unsafe { LLVMConstPointerCast(addr, llvm_ty) },
+                    None => unsafe { LLVMConstPtrToInt(addr, llvm_ty) },
+                });
+            }
+            let value = match init.const_value() {
+                Some(value) => value,
+                None => {
+                    return semantic(
+                        init.span(),
+                        format!("Initializer for `{}` is not a constant", name),
+                    )
+                }
+            };
+            let int = unsafe { LLVMConstInt(LLVMInt32TypeInContext(llvm.ctx), value as u64, 1) };
+            Ok(match (ty.pointee(), ty.int_bits()) {
+                (Some(_), _) => unsafe { LLVMConstIntToPtr(int, llvm_ty) },
+                (None, Some(bits)) if bits < 32 => unsafe { LLVMConstTrunc(int, llvm_ty) },
+                _ => int,
+            })
+        }
+    }
+}
+
+impl Global {
+    // zeroed globals go to .bss, const ones to .rodata and the rest to .data
+    fn codegen(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, CodegenError> {
+        let llvm_ty = llvm.llvm_type(&self.ty);
+        let init = match &self.init {
+            Some(init) => const_initializer(init, &self.ty, &self.name, llvm)?,
+            None => unsafe { LLVMConstNull(llvm_ty) },
+        };
+        let name = cstr(&self.name);
+        let val = unsafe { LLVMAddGlobal(llvm.module, llvm_ty, name.as_ptr()) };
+        unsafe {
+            LLVMSetInitializer(val, init);
+            if self.is_static {
+                LLVMSetLinkage(val, LLVMLinkage::LLVMInternalLinkage);
+            }
+            // a volatile object may change under the program's feet
+            if self.ty.is_const() && !self.ty.is_volatile() {
+                LLVMSetGlobalConstant(val, 1);
+            }
+        }
+        llvm.declare(
+            &self.name,
+            Scoped {
+                val,
+                ty: self.ty.clone(),
+            },
+        );
+        Ok(val)
     }
 }
 
 impl Program {
-    fn codegen(&self, llvm: &mut LLVM) -> Result<LLVMValueRef, String> {
-        let mut ir: Result<*mut llvm_sys::LLVMValue, String> =
-            Err("No functions in program".to_string());
+    fn codegen(&self, llvm: &mut LLVM) -> Result<(), CodegenError> {
+        // declare every signature first so bodies can call functions defined later
         for func in &self.functions {
-            ir = func.codegen(llvm);
+            func.declare(llvm)?;
+        }
+        // globals live in a file scope below every function's
+        llvm.push_scope();
+        for item in self.items() {
+            match item {
+                Item::Global(global) => {
+                    global.codegen(llvm)?;
+                }
+                Item::Function(func) if !func.prototype => {
+                    func.codegen(llvm)?;
+                }
+                Item::Function(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// check the module before handing it to the backend, naming the first
+// function the verifier rejects
+fn verify(llvm: &LLVM) -> Result<(), CodegenError> {
+    let mut msg = std::ptr::null_mut();
+    let broken = unsafe {
+        LLVMVerifyModule(
+            llvm.module,
+            LLVMVerifierFailureAction::LLVMReturnStatusAction,
+            &mut msg,
+        )
+    };
+    let message = unsafe { take_message(msg) };
+    if broken == 0 {
+        return Ok(());
+    }
+    let mut func = unsafe { LLVMGetFirstFunction(llvm.module) };
+    while !func.is_null() {
+        let failed =
+            unsafe { LLVMVerifyFunction(func, LLVMVerifierFailureAction::LLVMReturnStatusAction) };
+        if failed != 0 {
+            let mut len = 0;
+            let name = unsafe { CStr::from_ptr(LLVMGetValueName2(func, &mut len)) };
+            return Err(CodegenError::Verify {
+                function: Some(name.to_string_lossy().into_owned()),
+                message,
+            });
+        }
+        func = unsafe { LLVMGetNextFunction(func) };
+    }
+    Err(CodegenError::Verify {
+        function: None,
+        message,
+    })
+}
+
+/// The triple compiled for when none is given.
+pub const DEFAULT_TRIPLE: &str = "armv4t-unknown-linux-gnueabi";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+}
+
+impl OptLevel {
+    fn pipeline(self) -> &'static str {
+        match self {
+            OptLevel::O0 => "default<O0>",
+            OptLevel::O1 => "default<O1>",
+            OptLevel::O2 => "default<O2>",
+            OptLevel::O3 => "default<O3>",
+            OptLevel::Os => "default<Os>",
         }
-        ir
     }
+
+    fn codegen_level(self) -> LLVMCodeGenOptLevel {
+        match self {
+            OptLevel::O0 => LLVMCodeGenOptLevel::LLVMCodeGenLevelNone,
+            OptLevel::O1 => LLVMCodeGenOptLevel::LLVMCodeGenLevelLess,
+            OptLevel::O2 | OptLevel::Os => LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
+            OptLevel::O3 => LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileType {
+    Asm,
+    Obj,
 }
 
-pub fn codegen(program: &Program, path: &str) {
+/// A machine to compile for, built from a target triple.
+pub struct Target {
+    triple: String,
+    opt_level: OptLevel,
+    machine: LLVMTargetMachineRef,
+}
+
+impl Target {
+    pub fn new(triple: &str, opt_level: OptLevel) -> Result<Target, CodegenError> {
+        unsafe {
+            LLVM_InitializeAllTargetInfos();
+            LLVM_InitializeAllTargets();
+            LLVM_InitializeAllTargetMCs();
+            LLVM_InitializeAllAsmParsers();
+            LLVM_InitializeAllAsmPrinters();
+
+            let mut err_string = std::ptr::null_mut();
+            let mut target = std::ptr::null_mut();
+            let failed =
+                LLVMGetTargetFromTriple(cstr(triple).as_ptr(), &mut target, &mut err_string);
+            if failed != 0 {
+                return Err(CodegenError::UnknownTarget {
+                    triple: triple.to_string(),
+                    message: take_message(err_string),
+                });
+            }
+
+            let machine = LLVMCreateTargetMachine(
+                target,
+                cstr(triple).as_ptr(),
+                cstr("").as_ptr(),
+                cstr("").as_ptr(),
+                opt_level.codegen_level(),
+                LLVMRelocMode::LLVMRelocDefault,
+                LLVMCodeModel::LLVMCodeModelDefault,
+            );
+            Ok(Target {
+                triple: triple.to_string(),
+                opt_level,
+                machine,
+            })
+        }
+    }
+
+    pub fn triple(&self) -> &str {
+        &self.triple
+    }
+}
+
+impl Drop for Target {
+    fn drop(&mut self) {
+        unsafe { LLVMDisposeTargetMachine(self.machine) }
+    }
+}
+
+/// A verified LLVM module lowered from a [`Program`].
+pub struct Module {
+    llvm: LLVM,
+}
+
+impl Module {
+    /// The module as textual LLVM IR.
+    pub fn ir(&self) -> String {
+        unsafe { take_message(LLVMPrintModuleToString(self.llvm.module)) }
+    }
+
+    /// Specialise the module for `target` and run its optimization pipeline.
+    pub fn optimize(&mut self, target: &Target) -> Result<(), CodegenError> {
+        unsafe {
+            let layout = LLVMCreateTargetDataLayout(target.machine);
+            LLVMSetModuleDataLayout(self.llvm.module, layout);
+            LLVMDisposeTargetData(layout);
+            LLVMSetTarget(self.llvm.module, cstr(&target.triple).as_ptr());
+
+            let options = LLVMCreatePassBuilderOptions();
+            let err = LLVMRunPasses(
+                self.llvm.module,
+                cstr(target.opt_level.pipeline()).as_ptr(),
+                target.machine,
+                options,
+            );
+            LLVMDisposePassBuilderOptions(options);
+            if !err.is_null() {
+                return Err(CodegenError::Optimize {
+                    message: take_message(llvm_sys::error::LLVMGetErrorMessage(err)),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile the module for `target` into assembly or object code.
+    pub fn compile(&self, target: &Target, file_type: FileType) -> Result<Vec<u8>, CodegenError> {
+        let file_type = match file_type {
+            FileType::Asm => LLVMCodeGenFileType::LLVMAssemblyFile,
+            FileType::Obj => LLVMCodeGenFileType::LLVMObjectFile,
+        };
+        unsafe {
+            let mut err_string = std::ptr::null_mut();
+            let mut buf = std::ptr::null_mut();
+            let failed = LLVMTargetMachineEmitToMemoryBuffer(
+                target.machine,
+                self.llvm.module,
+                file_type,
+                &mut err_string,
+                &mut buf,
+            );
+            if failed != 0 {
+                return Err(CodegenError::Compile {
+                    triple: target.triple.clone(),
+                    message: take_message(err_string),
+                });
+            }
+            let start = LLVMGetBufferStart(buf) as *const u8;
+            let bytes = std::slice::from_raw_parts(start, LLVMGetBufferSize(buf)).to_vec();
+            LLVMDisposeMemoryBuffer(buf);
+            Ok(bytes)
+        }
+    }
+
+    /// Optimize the module for `target` and write it as assembly to `path`.
+    pub fn emit(&mut self, target: &Target, path: &str) -> Result<(), CodegenError> {
+        self.optimize(target)?;
+        let asm = self.compile(target, FileType::Asm)?;
+        std::fs::write(path, asm).map_err(|err| CodegenError::Emit {
+            path: path.to_string(),
+            message: err.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ir())
+    }
+}
+
+/// Lower a program to LLVM IR and verify the result.
+pub fn lower(program: &Program) -> Result<Module, CodegenError> {
     let mut llvm = LLVM::new();
-    unsafe {
-        LLVM_InitializeAllTargetInfos();
-        LLVM_InitializeAllTargets();
-        LLVM_InitializeAllTargetMCs();
-        LLVM_InitializeAllAsmParsers();
-        LLVM_InitializeAllAsmPrinters();
-
-        program.codegen(&mut llvm).unwrap();
-        let target_triple = LLVMCreateMessage(cstr("armv4t-unknown-linux-gnueabi").as_ptr());
-        let mut err_string = std::mem::MaybeUninit::uninit();
-        let mut target = std::ptr::null_mut();
-        let ok = llvm_sys::target_machine::LLVMGetTargetFromTriple(
-            target_triple,
-            &mut target,
-            err_string.as_mut_ptr(),
+    program.codegen(&mut llvm)?;
+    verify(&llvm)?;
+    Ok(Module { llvm })
+}
+
+/// Lower a program and write it as unoptimized assembly for the default
+/// target to `path`, as the driver does without `-O`.
+pub fn codegen(program: &Program, path: &str) -> Result<(), CodegenError> {
+    let target = Target::new(DEFAULT_TRIPLE, OptLevel::O0)?;
+    lower(program)?.emit(&target, path)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    // build and verify a module, returning its textual IR
+    pub fn verified_ir(program: &Program) -> Result<String, CodegenError> {
+        super::lower(program).map(|module| module.ir())
+    }
+
+    fn lower(code: &str) -> Result<String, CodegenError> {
+        verified_ir(&parse(code).unwrap())
+    }
+
+    // the instructions of the basic block labelled `label` in `ir`
+    fn block<'a>(ir: &'a str, label: &str) -> Vec<&'a str> {
+        ir.lines()
+            .skip_while(|line| line.split(':').next() != Some(label))
+            .skip(1)
+            .take_while(|line| line.starts_with("  "))
+            .map(str::trim)
+            .collect()
+    }
+
+    // where the unconditional branch ending block `label` goes
+    fn branch_target<'a>(ir: &'a str, label: &str) -> &'a str {
+        let last = block(ir, label).pop().unwrap();
+        last.strip_prefix("br label %").unwrap()
+    }
+
+    // the value and destination of each case of the `switch` in `ir`
+    fn switch_cases(ir: &str) -> Vec<(i32, &str)> {
+        ir.lines()
+            .skip_while(|line| !line.trim_start().starts_with("switch "))
+            .skip(1)
+            .take_while(|line| line.trim() != "]")
+            .map(|line| {
+                let case = line.trim().strip_prefix("i32 ").unwrap();
+                let (value, dest) = case.split_once(", label %").unwrap();
+                (value.parse().unwrap(), dest)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let ir = lower(
+            "int main() { int a = 1; if (a) { int a = 2; a = 3; } else { a = 4; } return a; }",
+        )
+        .unwrap();
+        assert!(ir.contains("store i32 3, i32* %a2,"));
+        assert!(ir.contains("store i32 4, i32* %a,"));
+    }
+
+    #[test]
+    fn test_bool_to_int() {
+        let ir = lower("int f(int x) { int b = x < 3; b = x > 4; return (x == b) + f(x != 1); }")
+            .unwrap();
+        assert!(ir.contains("zext i1"));
+    }
+
+    #[test]
+    fn test_int_to_bool() {
+        let ir = lower(
+            "int main(int x) { while (x) { x = x - 1; } if (x - 1 && 2 < 3 || x) { return 1; } else { return x; } }",
+        )
+        .unwrap();
+        assert!(ir.contains("icmp ne i32"));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let ir = lower("int main() { return *0x400 && *0x404 || *0x408; }").unwrap();
+        let block = |label: &str| {
+            let start = ir.find(&format!("\n{}:", label)).unwrap();
+            let end = ir[start + 1..].find("\n\n").unwrap();
+            ir[start..start + 1 + end].to_string()
+        };
+        // each later read sits behind the branch that guards it
+        let entry = block("entry");
+        assert!(entry.contains("1024") && !entry.contains("1028") && !entry.contains("1032"));
+        assert!(entry.contains("load volatile"));
+        assert!(block("rhs").contains("1028"));
+        assert!(block("rhs3").contains("1032"));
+        assert!(ir.contains("phi i1 [ false, %entry ]"));
+        assert!(ir.contains("phi i1 [ true, %logicmerge ]"));
+    }
+
+    #[test]
+    fn test_void_function() {
+        let ir = lower(
+            "void put(int c) { if (c == 0) { return; } else { *0x400 = c; } } int main(void) { put(65); return 0; }",
+        )
+        .unwrap();
+        assert!(ir.contains("define void @put(i32 %c)"));
+        assert!(ir.contains("ret void"));
+        assert!(ir.contains("call void @put(i32 65)"));
+        assert!(ir.contains("define i32 @main()"));
+    }
+
+    #[test]
+    fn test_nested_return() {
+        let ir = lower(
+            "int main(int x) { while (x) { if (x > 2) { if (x > 3) { return 1; } else { return 2; } x = 5; } x = x - 1; } return 0; }",
+        )
+        .unwrap();
+        // the dead store after the inner if/else is never emitted
+        assert!(!ir.contains("i32 5"));
+        assert_eq!(ir.matches("br label %retblock").count(), 3);
+    }
+
+    #[test]
+    fn test_return_in_loop() {
+        lower("int main() { while (1) { return 1; } }").unwrap();
+        lower("void f(int x) { if (x) { return; } while (x) { if (x) { return; } } }").unwrap();
+        lower("int g(int x) { if (x) { return 1; return 2; } else { x = 3; } return x; }").unwrap();
+    }
+
+    #[test]
+    fn test_void_value() {
+        let err = lower("void f() { return; } int main() { return f() + 1; }").unwrap_err();
+        assert_eq!(err.to_string(), "Void value of `f()` used");
+    }
+
+    #[test]
+    fn test_verify_names_function() {
+        let llvm = LLVM::new();
+        unsafe {
+            let fn_type =
+                LLVMFunctionType(LLVMVoidTypeInContext(llvm.ctx), std::ptr::null_mut(), 0, 0);
+            let func = LLVMAddFunction(llvm.module, cstr("bad").as_ptr(), fn_type);
+            // a block without a terminator
+            LLVMAppendBasicBlockInContext(llvm.ctx, func, cstr("entry").as_ptr());
+        }
+        let err = verify(&llvm).unwrap_err();
+        assert!(
+            matches!(&err, CodegenError::Verify { function: Some(name), .. } if name == "bad"),
+            "{:?}",
+            err
         );
-        if ok > 0 {
-            println!("Error: {:?}", CStr::from_ptr(err_string.assume_init()));
-            return;
-        }
-
-        let cpu = LLVMCreateMessage(cstr("").as_ptr());
-        let features = LLVMCreateMessage(cstr("").as_ptr());
-        let target_machine = LLVMCreateTargetMachine(
-            target,
-            target_triple,
-            cpu,
-            features,
-            LLVMCodeGenOptLevel::LLVMCodeGenLevelDefault,
-            LLVMRelocMode::LLVMRelocDefault,
-            LLVMCodeModel::LLVMCodeModelDefault,
+        assert!(err.to_string().starts_with("Invalid IR in function `bad`"));
+    }
+
+    #[test]
+    fn test_emit_error() {
+        let program = parse("int main() { return 0; }").unwrap();
+        let err = codegen(&program, "out/missing/dir/a.s").unwrap_err();
+        assert!(
+            matches!(&err, CodegenError::Emit { path, .. } if path == "out/missing/dir/a.s"),
+            "{:?}",
+            err
         );
+    }
 
-        let filename = LLVMCreateMessage(cstr(path).as_ptr());
-        err_string = std::mem::MaybeUninit::uninit();
-        LLVMDumpModule(llvm.module);
-        let ok = LLVMTargetMachineEmitToFile(
-            target_machine,
-            llvm.module,
-            filename,
-            LLVMCodeGenFileType::LLVMAssemblyFile,
-            err_string.as_mut_ptr(),
+    #[test]
+    fn test_emit_optimizes() {
+        let program = parse("int main() { int x = 2; return x + 1; }").unwrap();
+        let mut module = super::lower(&program).unwrap();
+        let target = Target::new(DEFAULT_TRIPLE, OptLevel::O2).unwrap();
+        std::fs::create_dir_all("out").unwrap();
+        module.emit(&target, "out/emit_optimizes.s").unwrap();
+        assert!(module.ir().contains("ret i32 3"), "{}", module.ir());
+    }
+
+    #[test]
+    fn test_unary() {
+        let ir = lower("int main() { int x = 3; int *p = &x; *p = -x; return !x + ~x; }").unwrap();
+        assert!(ir.contains("store i32* %x, i32** %p"), "{}", ir);
+        assert!(ir.contains("%negtmp = sub i32 0, %x"), "{}", ir);
+        assert!(ir.contains("%nottmp = xor i1 %tobool, true"), "{}", ir);
+        assert!(ir.contains("xor i32 %x"), "{}", ir);
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let ir = lower("int main(int x) { return (x % 3 & x | x ^ 5) << 1 >> 2; }").unwrap();
+        assert!(ir.contains("%remtmp = srem i32 %x2, 3"), "{}", ir);
+        assert!(ir.contains("%andtmp = and i32 %remtmp, %x3"), "{}", ir);
+        assert!(ir.contains("%xortmp = xor i32 %x4, 5"), "{}", ir);
+        assert!(ir.contains("%ortmp = or i32 %andtmp, %xortmp"), "{}", ir);
+        assert!(ir.contains("%shltmp = shl i32 %ortmp, 1"), "{}", ir);
+        assert!(ir.contains("%shrtmp = ashr i32 %shltmp, 2"), "{}", ir);
+    }
+
+    #[test]
+    fn test_compound_assign() {
+        let ir = lower("int main(int a) { *(a + 4) |= 1; }").unwrap();
+        assert_eq!(ir.matches("inttoptr").count(), 1, "{}", ir);
+        assert!(
+            ir.contains("%oldtmp = load volatile i32, i32* %deref"),
+            "{}",
+            ir
         );
-        if ok > 0 {
-            println!("Error: {:?}", CStr::from_ptr(err_string.assume_init()));
-        }
-        println!("Done");
+        assert!(ir.contains("%ortmp = or i32 %oldtmp, 1"), "{}", ir);
+        assert!(
+            ir.contains("store volatile i32 %ortmp, i32* %deref"),
+            "{}",
+            ir
+        );
+    }
+
+    #[test]
+    fn test_inc_dec() {
+        let ir = lower("int main() { int x = 1; int y = x++; return --y; }").unwrap();
+        assert!(ir.contains("%addtmp = add i32 %oldtmp, 1"), "{}", ir);
+        assert!(ir.contains("%subtmp = sub i32 %oldtmp1, 1"), "{}", ir);
+        assert!(ir.contains("store i32 %oldtmp, i32* %y"), "{}", ir);
+        assert!(ir.contains("store i32 %subtmp, i32* %ret"), "{}", ir);
+    }
+
+    #[test]
+    fn test_loops() {
+        let code = "int main() {
+            int n = 0;
+            for (int i = 0; i < 10; i++) {
+                if (i == 5) { break; }
+                do { n++; continue; } while (0);
+            }
+            return n;
+        }";
+        let ir = lower(code).unwrap();
+        // `break` leaves the `for`, `continue` goes to the `do` condition
+        assert!(ir.contains("  br label %merge\n\nelse:"), "{}", ir);
+        let cond = ir
+            .split("store i32 %addtmp, i32* %n, align 4\n  br label %")
+            .nth(1)
+            .and_then(|rest| rest.lines().next())
+            .unwrap();
+        let test = block(&ir, cond).pop().unwrap();
+        assert!(test.starts_with("br i1 false, "), "{}", ir);
+        let done = test.rsplit("label %").next().unwrap();
+        assert_eq!(branch_target(&ir, done), "step", "{}", ir);
+        assert!(ir.trim_end().ends_with("br label %step\n}"), "{}", ir);
+    }
+
+    #[test]
+    fn test_switch() {
+        let code = "int main(int x) {
+            while (x) {
+                switch (x) {
+                    case 1: case -2: x = 4; break;
+                    case 3: continue;
+                    default: x--;
+                }
+            }
+            return x;
+        }";
+        let ir = lower(code).unwrap();
+        assert!(ir.contains(", label %default ["), "{}", ir);
+        let cases = switch_cases(&ir);
+        let values: Vec<i32> = cases.iter().map(|(value, _)| *value).collect();
+        assert_eq!(values, vec![1, -2, 3], "{}", ir);
+        let dests: HashSet<&str> = cases.iter().map(|(_, dest)| *dest).collect();
+        assert_eq!(dests.len(), 3, "{}", ir);
+        // the first label falls through, `continue` skips the switch
+        assert_eq!(branch_target(&ir, cases[0].1), cases[1].1, "{}", ir);
+        assert_eq!(branch_target(&ir, cases[2].1), "cond", "{}", ir);
+    }
+
+    #[test]
+    fn test_duplicate_case() {
+        let result = lower("int main(int x) { switch (x) { case 1: case 2 - 1: return 0; } }");
+        assert!(
+            matches!(result, Err(CodegenError::Semantic { .. })),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_conditional() {
+        let ir = lower("int main(int x) { return x ? x + 1 : x && 2; }").unwrap();
+        assert!(
+            ir.contains("br i1 %tobool, label %then, label %else"),
+            "{}",
+            ir
+        );
+        assert!(
+            ir.contains("%condtmp = phi i32 [ %addtmp, %then ], [ %booltmp, %logicmerge ]"),
+            "{}",
+            ir
+        );
+
+        let ir = lower("int main(int x) { return 2 - 2 ? x : x * 3; }").unwrap();
+        assert!(!ir.contains("then"), "{}", ir);
+        assert!(ir.contains("%multmp = mul i32 %x2, 3"), "{}", ir);
+    }
+
+    #[test]
+    fn test_pointers() {
+        let code = "int main() {
+            int a = 5;
+            int *p = &a;
+            char *s = p;
+            volatile int *reg = p;
+            *reg = 1;
+            s = s + 3;
+            p = p - 2;
+            p++;
+            return (p - &a) + (p != 0) + *s + (p < &a);
+        }";
+        let ir = lower(code).unwrap();
+        // only accesses through a volatile type are volatile
+        assert!(ir.contains("store i32* %a, i32** %p,"), "{}", ir);
+        assert!(ir.contains("store volatile i32 1, i32* %reg3,"), "{}", ir);
+        // arithmetic steps over whole elements
+        assert!(ir.contains("getelementptr i8, i8* %s4, i32 3"), "{}", ir);
+        assert!(ir.contains("getelementptr i32, i32* %p5, i32 -2"), "{}", ir);
+        assert!(
+            ir.contains("getelementptr i32, i32* %oldtmp, i32 1"),
+            "{}",
+            ir
+        );
+        assert!(ir.contains("%difftmp = sdiv exact i32 %subtmp,"), "{}", ir);
+        assert!(ir.contains("icmp ne i32* %p11, null"), "{}", ir);
+        assert!(ir.contains("sext i8 %deref to i32"), "{}", ir);
+        assert!(ir.contains("icmp ult i32* %p14, %a"), "{}", ir);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let code = "int sum(int v[], int n) { return v[n - 1]; }
+        int main() {
+            int a[] = {1, 2, 3};
+            char c[2][4];
+            c[1][2] = 7;
+            return sum(a, 3) + a[1] + c[1][2];
+        }";
+        let ir = lower(code).unwrap();
+        // missing elements are zero, the rest are stored one by one
+        assert!(
+            ir.contains("store [3 x i32] zeroinitializer, [3 x i32]* %a,"),
+            "{}",
+            ir
+        );
+        assert!(
+            ir.contains("getelementptr [3 x i32], [3 x i32]* %a, i32 0, i32 2"),
+            "{}",
+            ir
+        );
+        // parameters are pointers and arrays decay when passed
+        assert!(ir.contains("define i32 @sum(i32* %v, i32 %n)"), "{}", ir);
+        assert!(ir.contains("call i32 @sum(i32* %elemtmp"), "{}", ir);
+        // indexing steps through the row before the element
+        assert!(
+            ir.contains("getelementptr [4 x i8], [4 x i8]* %elemtmp3, i32 1"),
+            "{}",
+            ir
+        );
+        assert!(ir.contains("store i8 7, i8* %ptrtmp"), "{}", ir);
+    }
+
+    #[test]
+    fn test_globals() {
+        let code = "static int count;
+        int ticks = 5;
+        const char table[3] = {1, 2};
+        volatile unsigned *reg = 64;
+        int main() {
+            count = count + ticks;
+            *reg = 1;
+            return count + table[1];
+        }";
+        let program = parse(code).unwrap();
+        let module = super::lower(&program).unwrap();
+        let ir = module.ir();
+        assert!(ir.contains("@count = internal global i32 0"), "{}", ir);
+        assert!(ir.contains("@ticks = global i32 5"), "{}", ir);
+        assert!(
+            ir.contains("@table = constant [3 x i8] c\"\\01\\02\\00\""),
+            "{}",
+            ir
+        );
+        assert!(
+            ir.contains("@reg = global i32* inttoptr (i32 64 to i32*)"),
+            "{}",
+            ir
+        );
+        assert!(ir.contains("store i32 %addtmp, i32* @count,"), "{}", ir);
+        // zeroed, initialized and read-only data each get their own section
+        let target = Target::new(DEFAULT_TRIPLE, OptLevel::O0).unwrap();
+        let asm = module.compile(&target, FileType::Asm).unwrap();
+        let asm = String::from_utf8(asm).unwrap();
+        assert!(asm.contains(".local\tcount"), "{}", asm);
+        assert!(asm.contains(".data\n\t.globl\tticks"), "{}", asm);
+        assert!(asm.contains(".section\t.rodata"), "{}", asm);
+    }
+
+    #[test]
+    fn test_global_addresses() {
+        let code = "int x; int buf[4]; int grid[2][3];
+        int *p = &x; int *q = buf; int *r = &buf[2]; int *s = grid[1];
+        int main() { return *p + *q + *r + *s; }";
+        let ir = lower(code).unwrap();
+        assert!(ir.contains("@p = global i32* @x"), "{}", ir);
+        assert!(
+            ir.contains("@q = global i32* getelementptr inbounds ([4 x i32], [4 x i32]* @buf, i32 0, i32 0)"),
+            "{}",
+            ir
+        );
+        assert!(
+            ir.contains("@r = global i32* getelementptr inbounds ([4 x i32], [4 x i32]* @buf, i32 0, i32 2)"),
+            "{}",
+            ir
+        );
+        assert!(
+            ir.contains("@s = global i32* getelementptr inbounds"),
+            "{}",
+            ir
+        );
+    }
+
+    #[test]
+    fn test_static_function() {
+        let ir =
+            lower("static int helper(void) { return 1; } int main() { return helper(); }").unwrap();
+        assert!(ir.contains("define internal i32 @helper()"), "{}", ir);
+        assert!(ir.contains("define i32 @main()"), "{}", ir);
+        // a file may hold nothing but data
+        let ir = lower("int ticks = 5; static int count;").unwrap();
+        assert!(ir.contains("@count = internal global i32 0"), "{}", ir);
+    }
+
+    #[test]
+    fn test_int_types() {
+        let code = "int main() {
+            unsigned char c = 250;
+            char s = c;
+            short h = 70000;
+            unsigned u = 7;
+            return (c + s) + (u / 2) + (u >> 1) + (u < s) + (h / 2) + (h >> 1);
+        }";
+        let ir = lower(code).unwrap();
+        // narrow values widen by their own signedness and truncate on store
+        assert!(ir.contains("store i8 -6, i8* %c,"), "{}", ir);
+        assert!(ir.contains("zext i8 %c1 to i32"), "{}", ir);
+        assert!(ir.contains("trunc i32 %widetmp to i8"), "{}", ir);
+        assert!(ir.contains("store i16 4464, i16* %h,"), "{}", ir);
+        assert!(ir.contains("sext i8 %s5 to i32"), "{}", ir);
+        // the operation follows the type the operands convert to
+        assert!(ir.contains("udiv i32 %u"), "{}", ir);
+        assert!(ir.contains("lshr i32 %u"), "{}", ir);
+        assert!(ir.contains("icmp ult i32 %u"), "{}", ir);
+        assert!(ir.contains("sdiv i32 %widetmp"), "{}", ir);
+        assert!(ir.contains("ashr i32 %widetmp"), "{}", ir);
+    }
+
+    #[test]
+    fn test_unsigned_values() {
+        let code = "int main() {
+            unsigned char c = 200;
+            int sum = c + 0;
+            return sum + 0xFFFFFFFF / 2 + (-1 < 0x80000000);
+        }";
+        let ir = lower(code).unwrap();
+        // an unsigned char widens to 200, not -56
+        assert!(ir.contains("zext i8 %c1 to i32"), "{}", ir);
+        assert!(!ir.contains("sext"), "{}", ir);
+        // literals above INT_MAX are unsigned, so the division and the
+        // comparison are too
+        assert!(ir.contains("2147483647"), "{}", ir);
+        assert!(!ir.contains("-2147483648"), "{}", ir);
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        let err = lower("int main() { break; }").unwrap_err();
+        assert_eq!(err.to_string(), "`break` outside of a loop or switch");
+    }
+
+    #[test]
+    fn test_not_an_lvalue() {
+        let err = lower("int main() { return &1; }").unwrap_err();
+        assert_eq!(err.to_string(), "`1` is not an lvalue");
+    }
+
+    #[test]
+    fn test_block_scope_alloca() {
+        let ir = lower("int main() { while (1) { int b = 2; } }").unwrap();
+        let entry = ir.split("cond:").next().unwrap();
+        assert!(entry.contains("%b = alloca i32"));
+    }
+
+    #[test]
+    fn test_block_scope() {
+        let code = "int main() { while (1) { int b = 2; } return b; }";
+        let at = code.rfind('b').unwrap();
+        assert_eq!(
+            lower(code).unwrap_err(),
+            CodegenError::Semantic {
+                message: "Unknown variable `b`".to_string(),
+                span: Span::new(at, at + 1),
+            }
+        );
+    }
+
+    #[test]
+    fn test_function_scope() {
+        let err = lower("int f(int x) { int y = x; return y; } int main() { return y + x; }")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown variable `y`");
     }
 }
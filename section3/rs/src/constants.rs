@@ -9,6 +9,12 @@ pub enum Op {
     Sub,
     Mul,
     Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
     Eq,
     Ne,
     Le,
@@ -17,11 +23,6 @@ pub enum Op {
     Gt,
     Or,
     And,
-    // Assign,
-    // AddAssign,
-    // SubAssign,
-    // MulAssign,
-    // DivAssign,
 }
 
 impl Deparse for Op {
@@ -31,17 +32,18 @@ impl Deparse for Op {
             Op::Sub => "-".to_string(),
             Op::Mul => "*".to_string(),
             Op::Div => "/".to_string(),
+            Op::Rem => "%".to_string(),
+            Op::BitAnd => "&".to_string(),
+            Op::BitOr => "|".to_string(),
+            Op::BitXor => "^".to_string(),
+            Op::Shl => "<<".to_string(),
+            Op::Shr => ">>".to_string(),
             Op::Eq => "==".to_string(),
             Op::Ne => "!=".to_string(),
             Op::Le => "<=".to_string(),
             Op::Ge => ">=".to_string(),
             Op::Lt => "<".to_string(),
             Op::Gt => ">".to_string(),
-            // Op::Assign => "=".to_string(),
-            // Op::AddAssign => "+=".to_string(),
-            // Op::SubAssign => "-=".to_string(),
-            // Op::MulAssign => "*=".to_string(),
-            // Op::DivAssign => "/=".to_string(),
             Op::Or => "||".to_string(),
             Op::And => "&&".to_string(),
         }
@@ -56,24 +58,62 @@ impl fmt::Display for Op {
 
 impl Arbitrary for Op {
     fn arbitrary(g: &mut quickcheck::Gen) -> Self {
-        match u32::arbitrary(g) % 4 {
+        match u32::arbitrary(g) % 10 {
             0 => Op::Add,
             1 => Op::Sub,
             2 => Op::Mul,
             3 => Op::Div,
-            4 => Op::Eq,
-            5 => Op::Ne,
-            6 => Op::Le,
-            7 => Op::Ge,
-            8 => Op::Lt,
-            9 => Op::Gt,
-            10 => Op::Or,
-            11 => Op::And,
-            // 10 => Op::Assign,
-            // 11 => Op::AddAssign,
-            // 12 => Op::SubAssign,
-            // 13 => Op::MulAssign,
-            // 14 => Op::DivAssign,
+            4 => Op::Rem,
+            5 => Op::BitAnd,
+            6 => Op::BitOr,
+            7 => Op::BitXor,
+            8 => Op::Shl,
+            9 => Op::Shr,
+            10 => Op::Eq,
+            11 => Op::Ne,
+            12 => Op::Le,
+            13 => Op::Ge,
+            14 => Op::Lt,
+            15 => Op::Gt,
+            16 => Op::Or,
+            17 => Op::And,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+    AddrOf,
+}
+
+impl Deparse for UnaryOp {
+    fn deparse(&self) -> String {
+        match self {
+            UnaryOp::Neg => "-".to_string(),
+            UnaryOp::Not => "!".to_string(),
+            UnaryOp::BitNot => "~".to_string(),
+            UnaryOp::AddrOf => "&".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.deparse())
+    }
+}
+
+impl Arbitrary for UnaryOp {
+    // address-of needs a variable, which arbitrary expressions never have
+    fn arbitrary(g: &mut quickcheck::Gen) -> Self {
+        match u32::arbitrary(g) % 3 {
+            0 => UnaryOp::Neg,
+            1 => UnaryOp::Not,
+            2 => UnaryOp::BitNot,
             _ => unreachable!(),
         }
     }
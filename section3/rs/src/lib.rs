@@ -0,0 +1,57 @@
+//! A small C compiler targeting 32-bit ARM.
+//!
+//! Compilation is a pipeline of four stages. Each stage returns a value that
+//! can be inspected before it is handed to the next one:
+//!
+//! ```no_run
+//! let program = rs::parse("int main() { return 0; }").expect("syntax error");
+//! let warnings = rs::check(&program).expect("semantic errors");
+//! let mut module = rs::lower(&program).expect("lowering failed");
+//! println!("{} warnings\n{}", warnings.len(), module.ir());
+//! let target = rs::Target::new(rs::DEFAULT_TRIPLE, rs::OptLevel::O2).expect("no target");
+//! module.emit(&target, "main.s").expect("emit failed");
+//! ```
+//!
+//! For object code rather than assembly, use [`Module::optimize`] and
+//! [`Module::compile`] instead of [`emit`].
+
+pub mod codegen;
+pub mod constants;
+pub mod parser;
+pub mod sema;
+pub mod span;
+
+#[cfg(test)]
+extern crate quickcheck;
+
+pub use codegen::{CodegenError, FileType, Module, OptLevel, Target, DEFAULT_TRIPLE};
+pub use parser::{ParseError, Program};
+pub use sema::SemaError;
+pub use span::{Source, Span};
+
+/// Parse source code into a [`Program`], or report every syntax error found.
+pub fn parse(code: &str) -> Result<Program, Vec<ParseError>> {
+    parser::parse(code)
+}
+
+/// Check names, arity and types in a parsed program.
+///
+/// Returns the warnings if the program may be compiled, otherwise every
+/// diagnostic found, warnings included, in source order.
+pub fn check(program: &Program) -> Result<Vec<SemaError>, Vec<SemaError>> {
+    let diagnostics = sema::check(program);
+    if diagnostics.iter().any(|d| !d.is_warning()) {
+        return Err(diagnostics);
+    }
+    Ok(diagnostics)
+}
+
+/// Lower a checked program to a verified LLVM [`Module`].
+pub fn lower(program: &Program) -> Result<Module, CodegenError> {
+    codegen::lower(program)
+}
+
+/// Optimize a module for `target` and write it as assembly to `path`.
+pub fn emit(module: &mut Module, target: &Target, path: &str) -> Result<(), CodegenError> {
+    module.emit(target, path)
+}
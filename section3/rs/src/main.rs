@@ -1,38 +1,266 @@
-mod codegen;
-mod constants;
-mod parser;
-
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, Read, Write};
+use std::process::{self, Command};
 
-// read a file from argv[1]
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 2 {
-        println!("Usage: {} <filename> or stdin", args[0]);
-        return;
+use rs::{CodegenError, FileType, OptLevel, Source, Target};
+
+const USAGE: &str = "Usage: rs [options] <file>
+
+Reads `-` as stdin.
+
+Options:
+  -o <file>       write output to <file>, `-` for stdout
+  --emit=<kind>   one of ast, ir, asm (default), obj, bin
+  -O<level>       one of 0 (default), 1, 2, 3, s
+  --target=<triple>
+                  compile for <triple>, default armv4t-unknown-linux-gnueabi
+  -h, --help      print this message
+
+`bin` links with $CC, or `cc` when unset.";
+
+// exit codes
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Emit {
+    Ast,
+    Ir,
+    Asm,
+    Obj,
+    Bin,
+}
+
+impl Emit {
+    fn default_output(self) -> &'static str {
+        match self {
+            Emit::Ast => "-",
+            Emit::Ir => "a.ll",
+            Emit::Asm => "a.s",
+            Emit::Obj => "a.o",
+            Emit::Bin => "a.out",
+        }
     }
+}
 
-    let code = if args.len() == 2 {
-        fs::read_to_string(&args[1]).expect("Unable to read file")
-    } else {
+#[derive(Debug, PartialEq)]
+struct Options {
+    input: String,
+    output: String,
+    emit: Emit,
+    opt_level: OptLevel,
+    target: String,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut emit = Emit::Asm;
+    let mut opt_level = OptLevel::O0;
+    let mut target = rs::DEFAULT_TRIPLE.to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(path) => output = Some(path.clone()),
+                None => return Err("`-o` needs a file name".to_string()),
+            },
+            "--target" => match args.next() {
+                Some(triple) => target = triple.clone(),
+                None => return Err("`--target` needs a triple".to_string()),
+            },
+            "-O0" => opt_level = OptLevel::O0,
+            "-O1" => opt_level = OptLevel::O1,
+            "-O2" => opt_level = OptLevel::O2,
+            "-O3" => opt_level = OptLevel::O3,
+            "-Os" => opt_level = OptLevel::Os,
+            arg if arg.starts_with("--emit=") => {
+                emit = match &arg["--emit=".len()..] {
+                    "ast" => Emit::Ast,
+                    "ir" => Emit::Ir,
+                    "asm" => Emit::Asm,
+                    "obj" => Emit::Obj,
+                    "bin" => Emit::Bin,
+                    kind => return Err(format!("unknown emit kind `{}`", kind)),
+                }
+            }
+            arg if arg.starts_with("--target=") => target = arg["--target=".len()..].to_string(),
+            arg if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option `{}`", arg))
+            }
+            arg => {
+                if input.is_some() {
+                    return Err("more than one input file".to_string());
+                }
+                input = Some(arg.to_string());
+            }
+        }
+    }
+    let input = input.ok_or_else(|| "no input file".to_string())?;
+    let output = output.unwrap_or_else(|| emit.default_output().to_string());
+    Ok(Options {
+        input,
+        output,
+        emit,
+        opt_level,
+        target,
+    })
+}
+
+fn read_input(input: &str) -> io::Result<String> {
+    if input == "-" {
         let mut code = String::new();
-        io::stdin()
-            .read_line(&mut code)
-            .expect("Unable to read stdin");
-        code
+        io::stdin().read_to_string(&mut code)?;
+        return Ok(code);
+    }
+    fs::read_to_string(input)
+}
+
+fn write_output(output: &str, bytes: &[u8]) -> Result<(), String> {
+    let written = if output == "-" {
+        io::stdout().write_all(bytes)
+    } else {
+        fs::write(output, bytes)
     };
+    written.map_err(|err| format!("unable to write `{}`: {}", output, err))
+}
 
-    match parser::parse(&code) {
-        Ok(program) => {
-            println!("Parsed program: {:#?}", program);
-            println!("Generated:");
-            codegen::codegen(&program, "a.out");
+// link an object file into an executable with the system compiler driver
+fn link(object: &[u8], output: &str) -> Result<(), String> {
+    if output == "-" {
+        return Err("cannot write a binary to stdout".to_string());
+    }
+    let path = env::temp_dir().join(format!("rs-{}.o", process::id()));
+    fs::write(&path, object).map_err(|err| format!("unable to write object file: {}", err))?;
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&cc).arg(&path).arg("-o").arg(output).status();
+    let _ = fs::remove_file(&path);
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("linker `{}` failed with {}", cc, status)),
+        Err(err) => Err(format!("unable to run linker `{}`: {}", cc, err)),
+    }
+}
+
+const ABORTING: &str = "aborting due to previous errors";
+
+// codegen errors that point into the program are reported like the others
+fn report_codegen(source: &Source, err: CodegenError) -> String {
+    match err.span() {
+        Some(span) => {
+            eprintln!("{}", source.render(span, &format!("error: {}", err)));
+            ABORTING.to_string()
+        }
+        None => err.to_string(),
+    }
+}
+
+fn compile(options: &Options) -> Result<(), String> {
+    let code = read_input(&options.input)
+        .map_err(|err| format!("unable to read `{}`: {}", options.input, err))?;
+    let name = if options.input == "-" {
+        "<stdin>"
+    } else {
+        &options.input
+    };
+    let source = Source { name, code: &code };
+    let program = rs::parse(&code).map_err(|errors| {
+        for err in errors {
+            eprintln!("{}", source.render(err.span, &err.to_string()));
+        }
+        ABORTING.to_string()
+    })?;
+    if options.emit == Emit::Ast {
+        return write_output(&options.output, format!("{:#?}\n", program).as_bytes());
+    }
+    let report = |e: &rs::SemaError| eprintln!("{}", source.render(e.span(), &e.to_string()));
+    match rs::check(&program) {
+        Ok(warnings) => warnings.iter().for_each(report),
+        Err(errors) => {
+            errors.iter().for_each(report);
+            return Err(ABORTING.to_string());
         }
-        Err(msg) => println!("{}", msg),
+    }
+    let target = Target::new(&options.target, options.opt_level).map_err(|e| e.to_string())?;
+    let mut module = rs::lower(&program).map_err(|e| report_codegen(&source, e))?;
+    module.optimize(&target).map_err(|e| e.to_string())?;
+    let file_type = match options.emit {
+        Emit::Ast => unreachable!(),
+        Emit::Ir => return write_output(&options.output, format!("{}\n", module).as_bytes()),
+        Emit::Asm => FileType::Asm,
+        Emit::Obj | Emit::Bin => FileType::Obj,
+    };
+    let bytes = module
+        .compile(&target, file_type)
+        .map_err(|e| e.to_string())?;
+    if options.emit == Emit::Bin {
+        return link(&bytes, &options.output);
+    }
+    write_output(&options.output, &bytes)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = parse_args(&args).unwrap_or_else(|msg| {
+        eprintln!("error: {}\n\n{}", msg, USAGE);
+        process::exit(EXIT_USAGE);
+    });
+    if let Err(msg) = compile(&options) {
+        eprintln!("error: {}", msg);
+        process::exit(EXIT_FAILURE);
     }
 }
 
 #[cfg(test)]
-extern crate quickcheck;
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(
+            parse_args(&args("main.c")),
+            Ok(Options {
+                input: "main.c".to_string(),
+                output: "a.s".to_string(),
+                emit: Emit::Asm,
+                opt_level: OptLevel::O0,
+                target: rs::DEFAULT_TRIPLE.to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(
+            parse_args(&args(
+                "--emit=obj -Os - -o out.o --target x86_64-unknown-linux-gnu"
+            )),
+            Ok(Options {
+                input: "-".to_string(),
+                output: "out.o".to_string(),
+                emit: Emit::Obj,
+                opt_level: OptLevel::Os,
+                target: "x86_64-unknown-linux-gnu".to_string(),
+            })
+        );
+        assert_eq!(parse_args(&args("--emit=ir a.c")).unwrap().output, "a.ll");
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        assert!(parse_args(&args("")).is_err());
+        assert!(parse_args(&args("a.c b.c")).is_err());
+        assert!(parse_args(&args("a.c -o")).is_err());
+        assert!(parse_args(&args("a.c --emit=exe")).is_err());
+        assert!(parse_args(&args("a.c -O4")).is_err());
+    }
+}
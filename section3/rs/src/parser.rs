@@ -1,16 +1,169 @@
+use std::cell::RefCell;
+use std::fmt;
+
 use super::constants::*;
-use backtrace::Backtrace;
-use quickcheck::{Arbitrary, Gen, QuickCheck};
+use super::span::Span;
+use quickcheck::{Arbitrary, Gen};
 use rand::distributions::{Alphanumeric, DistString};
 
 pub trait Deparse {
     fn deparse(&self) -> String;
 }
 
+// integers have the sizes of an ILP32 target, `long` is as wide as `int`
+// and `char` is signed
 #[derive(Clone, Debug, PartialEq)]
 pub enum Type {
     Int,
     Void,
+    Char,
+    UChar,
+    Short,
+    UShort,
+    UInt,
+    Long,
+    ULong,
+    Pointer(Box<Type>),
+    // an array decays to a pointer to its first element wherever its value
+    // is taken
+    Array(Box<Type>, u32),
+    // accesses through a volatile lvalue are all kept and never reordered,
+    // as memory mapped IO needs
+    Volatile(Box<Type>),
+    // a const object is only ever written by its initializer
+    Const(Box<Type>),
+}
+
+impl Type {
+    /// The type without its `volatile` and `const` qualifiers.
+    pub fn unqualified(&self) -> &Type {
+        match self {
+            Type::Volatile(ty) | Type::Const(ty) => ty.unqualified(),
+            ty => ty,
+        }
+    }
+
+    /// Whether accesses to an object of this type are volatile, an array's
+    /// are when its elements' are.
+    pub fn is_volatile(&self) -> bool {
+        match self {
+            Type::Volatile(_) => true,
+            Type::Const(ty) | Type::Array(ty, _) => ty.is_volatile(),
+            _ => false,
+        }
+    }
+
+    /// Whether an object of this type is read-only, likewise for arrays.
+    pub fn is_const(&self) -> bool {
+        match self {
+            Type::Const(_) => true,
+            Type::Volatile(ty) | Type::Array(ty, _) => ty.is_const(),
+            _ => false,
+        }
+    }
+
+    pub fn is_void(&self) -> bool {
+        *self.unqualified() == Type::Void
+    }
+
+    /// The type a pointer points to, `None` for anything but a pointer.
+    pub fn pointee(&self) -> Option<&Type> {
+        match self.unqualified() {
+            Type::Pointer(to) => Some(to),
+            _ => None,
+        }
+    }
+
+    /// The pointer an array value turns into, any other type is unchanged.
+    pub fn decay(&self) -> Type {
+        match self.unqualified() {
+            Type::Array(elem, _) => Type::Pointer(elem.clone()),
+            _ => self.clone(),
+        }
+    }
+
+    /// `ty name` as it is declared, `int buf[64]` for an array.
+    pub fn declarator(&self, name: &str) -> String {
+        match self {
+            Type::Array(elem, len) => elem.declarator(&format!("{}[{}]", name, len)),
+            ty => format!("{} {}", ty.deparse(), name),
+        }
+    }
+
+    /// The width in bits of an integer type, `None` for anything else.
+    pub fn int_bits(&self) -> Option<u32> {
+        match self.unqualified() {
+            Type::Char | Type::UChar => Some(8),
+            Type::Short | Type::UShort => Some(16),
+            Type::Int | Type::UInt | Type::Long | Type::ULong => Some(32),
+            _ => None,
+        }
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(
+            self.unqualified(),
+            Type::UChar | Type::UShort | Type::UInt | Type::ULong
+        )
+    }
+
+    /// The unsigned integer type of the same width.
+    pub fn to_unsigned(&self) -> Type {
+        match self.unqualified() {
+            Type::Char => Type::UChar,
+            Type::Short => Type::UShort,
+            Type::Int => Type::UInt,
+            Type::Long => Type::ULong,
+            ty => ty.clone(),
+        }
+    }
+
+    /// The type of an integer literal, the first of `int`, `unsigned int`,
+    /// `long` and `unsigned long` that holds it; `long` is no wider than
+    /// `int`, so it is never needed.
+    pub fn literal(value: u32) -> Type {
+        if value <= i32::MAX as u32 {
+            Type::Int
+        } else {
+            Type::UInt
+        }
+    }
+
+    /// The type an integer is widened to before any arithmetic, every
+    /// narrower type fits in an `int`.
+    pub fn promoted(&self) -> Type {
+        match self.int_bits() {
+            Some(bits) if bits < 32 => Type::Int,
+            _ => self.unqualified().clone(),
+        }
+    }
+
+    /// The type of `lhs op rhs` for integer operands, which is also the
+    /// type the operation is done in, apart from comparisons.
+    pub fn arithmetic(op: Op, lhs: &Type, rhs: &Type) -> Type {
+        let (lhs, rhs) = (lhs.promoted(), rhs.promoted());
+        match op {
+            Op::Eq | Op::Ne | Op::Le | Op::Ge | Op::Lt | Op::Gt | Op::Or | Op::And => Type::Int,
+            Op::Shl | Op::Shr => lhs,
+            _ => Type::common(&lhs, &rhs),
+        }
+    }
+
+    /// The type both operands of a binary operator are converted to.
+    pub fn common(lhs: &Type, rhs: &Type) -> Type {
+        let (lhs, rhs) = (lhs.promoted(), rhs.promoted());
+        let either = |ty: Type| lhs == ty || rhs == ty;
+        if either(Type::ULong) || (either(Type::Long) && either(Type::UInt)) {
+            // a `long` can not hold every `unsigned int`
+            Type::ULong
+        } else if either(Type::Long) {
+            Type::Long
+        } else if either(Type::UInt) {
+            Type::UInt
+        } else {
+            Type::Int
+        }
+    }
 }
 
 impl Deparse for Type {
@@ -18,10 +171,29 @@ impl Deparse for Type {
         match self {
             Type::Int => "int".to_string(),
             Type::Void => "void".to_string(),
+            Type::Char => "char".to_string(),
+            Type::UChar => "unsigned char".to_string(),
+            Type::Short => "short".to_string(),
+            Type::UShort => "unsigned short".to_string(),
+            Type::UInt => "unsigned int".to_string(),
+            Type::Long => "long".to_string(),
+            Type::ULong => "unsigned long".to_string(),
+            Type::Pointer(to) => format!("{}*", to.deparse()),
+            Type::Array(elem, len) => format!("{}[{}]", elem.deparse(), len),
+            Type::Volatile(ty) if ty.pointee().is_some() => format!("{} volatile", ty.deparse()),
+            Type::Volatile(ty) => format!("volatile {}", ty.deparse()),
+            Type::Const(ty) if ty.pointee().is_some() => format!("{} const", ty.deparse()),
+            Type::Const(ty) => format!("const {}", ty.deparse()),
         }
     }
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.deparse())
+    }
+}
+
 fn readable_string(g: &mut Gen) -> String {
     Alphanumeric.sample_string(&mut rand::thread_rng(), g.size())
 }
@@ -30,6 +202,7 @@ fn readable_string(g: &mut Gen) -> String {
 pub struct Arg {
     pub ty: Type,
     pub name: String,
+    pub span: Span,
 }
 
 impl Deparse for Arg {
@@ -40,55 +213,162 @@ impl Deparse for Arg {
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expr {
-    Return {
+    // an expression evaluated for its side effects, `f(x);`
+    ExprStmt {
         expr: Box<Expr>,
+        span: Span,
+    },
+    Return {
+        expr: Option<Box<Expr>>,
+        span: Span,
     },
     Int {
         value: u32,
+        span: Span,
     },
     BinOp {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
         op: Op,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+        span: Span,
+    },
+    // `cond ? then : otherwise`, only the selected arm is evaluated
+    Conditional {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+        span: Span,
     },
     If {
         cond: Box<Expr>,
         then: Vec<Expr>,
         otherwise: Vec<Expr>,
+        span: Span,
     },
     Var {
         name: String,
+        span: Span,
     },
     Decl {
         ty: Type,
         name: String,
         init: Option<Box<Expr>>,
+        span: Span,
     },
     Assign {
         lhs: Box<Expr>,
         rhs: Box<Expr>,
+        span: Span,
+    },
+    // `lhs op= rhs`
+    CompoundAssign {
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        op: Op,
+        span: Span,
+    },
+    // `++` and `--` are `Add` and `Sub`, a prefix one gives the new value
+    IncDec {
+        op: Op,
+        prefix: bool,
+        expr: Box<Expr>,
+        span: Span,
     },
     Deref {
         addr: Box<Expr>,
+        span: Span,
+    },
+    // `base[index]`, which is `*(base + index)`
+    Index {
+        base: Box<Expr>,
+        index: Box<Expr>,
+        span: Span,
+    },
+    // `{1, 2, 3}`, only found as the initializer of a declaration
+    InitList {
+        values: Vec<Expr>,
+        span: Span,
     },
     While {
         cond: Box<Expr>,
         body: Vec<Expr>,
+        span: Span,
+    },
+    // `init` is a declaration or an expression statement, a missing `cond`
+    // loops forever
+    For {
+        init: Option<Box<Expr>>,
+        cond: Option<Box<Expr>>,
+        step: Option<Box<Expr>>,
+        body: Vec<Expr>,
+        span: Span,
+    },
+    DoWhile {
+        body: Vec<Expr>,
+        cond: Box<Expr>,
+        span: Span,
+    },
+    Break {
+        span: Span,
+    },
+    Continue {
+        span: Span,
+    },
+    // `case` and `default` are statements of the body, control falls
+    // through from one label into the next
+    Switch {
+        cond: Box<Expr>,
+        body: Vec<Expr>,
+        span: Span,
+    },
+    Case {
+        value: Box<Expr>,
+        span: Span,
+    },
+    Default {
+        span: Span,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        span: Span,
     },
 }
 
 impl Deparse for Expr {
     fn deparse(&self) -> String {
         match self {
-            Expr::Int { value } => value.to_string(),
-            Expr::BinOp { lhs, rhs, op } => {
+            Expr::ExprStmt { expr, .. } => expr.deparse(),
+            Expr::Int { value, .. } => value.to_string(),
+            Expr::BinOp { lhs, rhs, op, .. } => {
                 format!("({} {} {})", lhs.deparse(), op, rhs.deparse())
             }
-            Expr::Return { expr } => format!("return {}", expr.deparse()),
+            Expr::Unary { op, expr, .. } => format!("({}{})", op, expr.deparse()),
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => format!(
+                "({} ? {} : {})",
+                cond.deparse(),
+                then.deparse(),
+                otherwise.deparse()
+            ),
+            Expr::Return {
+                expr: Some(expr), ..
+            } => format!("return {}", expr.deparse()),
+            Expr::Return { expr: None, .. } => "return".to_string(),
             Expr::If {
                 cond,
                 then,
                 otherwise,
+                ..
             } => {
                 let then_str = then
                     .iter()
@@ -111,18 +391,33 @@ impl Deparse for Expr {
                 ty,
                 name,
                 init: Some(init),
-            } => format!("{} {} = {}", ty.deparse(), name, init.deparse()),
+                ..
+            } => format!("{} = {}", ty.declarator(name), init.deparse()),
             Expr::Decl {
                 ty,
                 name,
                 init: None,
-            } => format!("{} {}", ty.deparse(), name),
-            Expr::Assign { lhs, rhs } => {
+                ..
+            } => ty.declarator(name),
+            Expr::Assign { lhs, rhs, .. } => {
                 format!("{} = {}", lhs.deparse(), rhs.deparse())
             }
-            Expr::Var { name } => format!("{}", name),
-            Expr::Deref { addr } => format!("*{}", addr.deparse()),
-            Expr::While { cond, body } => {
+            Expr::CompoundAssign { lhs, rhs, op, .. } => {
+                format!("{} {}= {}", lhs.deparse(), op, rhs.deparse())
+            }
+            Expr::IncDec {
+                op, prefix, expr, ..
+            } => {
+                let glyph = if *op == Op::Add { "++" } else { "--" };
+                if *prefix {
+                    format!("({}{})", glyph, expr.deparse())
+                } else {
+                    format!("({}{})", expr.deparse(), glyph)
+                }
+            }
+            Expr::Var { name, .. } => name.to_string(),
+            Expr::Deref { addr, .. } => format!("*{}", addr.deparse()),
+            Expr::While { cond, body, .. } => {
                 let body_str = body
                     .iter()
                     .map(|e| e.deparse())
@@ -130,6 +425,67 @@ impl Deparse for Expr {
                     .join(";\n");
                 format!("while ({}) {{\n{};\n}}", cond.deparse(), body_str)
             }
+            Expr::For {
+                init,
+                cond,
+                step,
+                body,
+                ..
+            } => {
+                let part =
+                    |e: &Option<Box<Expr>>| e.as_ref().map_or(String::new(), |e| e.deparse());
+                let body_str = body
+                    .iter()
+                    .map(|e| e.deparse())
+                    .collect::<Vec<_>>()
+                    .join(";\n");
+                format!(
+                    "for ({}; {}; {}) {{\n{};\n}}",
+                    part(init),
+                    part(cond),
+                    part(step),
+                    body_str
+                )
+            }
+            Expr::DoWhile { body, cond, .. } => {
+                let body_str = body
+                    .iter()
+                    .map(|e| e.deparse())
+                    .collect::<Vec<_>>()
+                    .join(";\n");
+                format!("do {{\n{};\n}} while ({})", body_str, cond.deparse())
+            }
+            Expr::Break { .. } => "break".to_string(),
+            Expr::Continue { .. } => "continue".to_string(),
+            Expr::Switch { cond, body, .. } => {
+                let body_str = body
+                    .iter()
+                    .map(|e| e.deparse())
+                    .collect::<Vec<_>>()
+                    .join(";\n");
+                format!("switch ({}) {{\n{};\n}}", cond.deparse(), body_str)
+            }
+            Expr::Case { value, .. } => format!("case {}:", value.deparse()),
+            Expr::Index { base, index, .. } => {
+                format!("{}[{}]", base.deparse(), index.deparse())
+            }
+            Expr::InitList { values, .. } => {
+                let values_str = values
+                    .iter()
+                    .map(|e| e.deparse())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{{{}}}", values_str)
+            }
+            Expr::Default { .. } => "default:".to_string(),
+            Expr::Call { name, args, .. } => {
+                let args_str = args
+                    .iter()
+                    .map(|e| e.deparse())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("{}({})", name, args_str)
+            }
         }
     }
 }
@@ -140,10 +496,151 @@ impl Arbitrary for Expr {
             let op = Op::arbitrary(g);
             let lhs = Box::new(Expr::arbitrary(g));
             let rhs = Box::new(Expr::arbitrary(g));
-            return Expr::BinOp { lhs, rhs, op };
+            let span = Span::default();
+            return Expr::BinOp { lhs, rhs, op, span };
+        }
+        if u32::arbitrary(g) % 5 == 0 {
+            let op = UnaryOp::arbitrary(g);
+            let expr = Box::new(Expr::arbitrary(g));
+            let span = Span::default();
+            return Expr::Unary { op, expr, span };
+        }
+        // only the condition recurses, three growing children make the
+        // expressions deep enough to overflow the parser's stack
+        if u32::arbitrary(g) % 7 == 0 {
+            let cond = Box::new(Expr::arbitrary(g));
+            let then = Box::new(Expr::Int {
+                value: u32::arbitrary(g),
+                span: Span::default(),
+            });
+            let otherwise = Box::new(Expr::Int {
+                value: u32::arbitrary(g),
+                span: Span::default(),
+            });
+            let span = Span::default();
+            return Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                span,
+            };
         }
         let value = u32::arbitrary(g);
-        Expr::Int { value }
+        let span = Span::default();
+        Expr::Int { value, span }
+    }
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::ExprStmt { span, .. }
+            | Expr::Return { span, .. }
+            | Expr::Int { span, .. }
+            | Expr::BinOp { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Conditional { span, .. }
+            | Expr::If { span, .. }
+            | Expr::Var { span, .. }
+            | Expr::Decl { span, .. }
+            | Expr::Assign { span, .. }
+            | Expr::CompoundAssign { span, .. }
+            | Expr::IncDec { span, .. }
+            | Expr::Deref { span, .. }
+            | Expr::Index { span, .. }
+            | Expr::InitList { span, .. }
+            | Expr::While { span, .. }
+            | Expr::For { span, .. }
+            | Expr::DoWhile { span, .. }
+            | Expr::Break { span }
+            | Expr::Continue { span }
+            | Expr::Switch { span, .. }
+            | Expr::Case { span, .. }
+            | Expr::Default { span }
+            | Expr::Call { span, .. } => *span,
+        }
+    }
+
+    /// The value of an integer constant expression, `None` when it is not
+    /// one or its value is undefined, as for a division by zero.
+    pub fn const_value(&self) -> Option<i32> {
+        self.const_typed().map(|(value, _)| value)
+    }
+
+    // the value with the type it has, which decides how it folds further
+    fn const_typed(&self) -> Option<(i32, Type)> {
+        match self {
+            Expr::Int { value, .. } => Some((*value as i32, Type::literal(*value))),
+            Expr::Unary { op, expr, .. } => {
+                let (val, ty) = expr.const_typed()?;
+                match op {
+                    UnaryOp::Neg => Some((val.wrapping_neg(), ty.promoted())),
+                    UnaryOp::Not => Some(((val == 0) as i32, Type::Int)),
+                    UnaryOp::BitNot => Some((!val, ty.promoted())),
+                    UnaryOp::AddrOf => None,
+                }
+            }
+            Expr::BinOp { lhs, rhs, op, .. } => {
+                let (lhs, lhs_ty) = lhs.const_typed()?;
+                let (rhs, rhs_ty) = rhs.const_typed()?;
+                let ty = Type::arithmetic(*op, &lhs_ty, &rhs_ty);
+                // comparisons are done in the common type, not in `int`
+                let unsigned = match op {
+                    Op::Shl | Op::Shr => ty.is_unsigned(),
+                    _ => Type::common(&lhs_ty, &rhs_ty).is_unsigned(),
+                };
+                let (ul, ur) = (lhs as u32, rhs as u32);
+                let shift = u32::try_from(rhs).ok().filter(|&rhs| rhs < 32);
+                let value = match op {
+                    Op::Add => Some(lhs.wrapping_add(rhs)),
+                    Op::Sub => Some(lhs.wrapping_sub(rhs)),
+                    Op::Mul => Some(lhs.wrapping_mul(rhs)),
+                    Op::Div if unsigned => ul.checked_div(ur).map(|v| v as i32),
+                    Op::Div => lhs.checked_div(rhs),
+                    Op::Rem if unsigned => ul.checked_rem(ur).map(|v| v as i32),
+                    Op::Rem => lhs.checked_rem(rhs),
+                    Op::BitAnd => Some(lhs & rhs),
+                    Op::BitOr => Some(lhs | rhs),
+                    Op::BitXor => Some(lhs ^ rhs),
+                    Op::Shl => shift.map(|shift| lhs << shift),
+                    Op::Shr if unsigned => shift.map(|shift| (ul >> shift) as i32),
+                    Op::Shr => shift.map(|shift| lhs >> shift),
+                    Op::Eq => Some((lhs == rhs) as i32),
+                    Op::Ne => Some((lhs != rhs) as i32),
+                    Op::Le if unsigned => Some((ul <= ur) as i32),
+                    Op::Le => Some((lhs <= rhs) as i32),
+                    Op::Ge if unsigned => Some((ul >= ur) as i32),
+                    Op::Ge => Some((lhs >= rhs) as i32),
+                    Op::Lt if unsigned => Some((ul < ur) as i32),
+                    Op::Lt => Some((lhs < rhs) as i32),
+                    Op::Gt if unsigned => Some((ul > ur) as i32),
+                    Op::Gt => Some((lhs > rhs) as i32),
+                    Op::And => Some((lhs != 0 && rhs != 0) as i32),
+                    Op::Or => Some((lhs != 0 || rhs != 0) as i32),
+                };
+                value.map(|value| (value, ty))
+            }
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                let (then, otherwise) = (then.const_typed(), otherwise.const_typed());
+                let (value, ty) = match cond.const_value()? {
+                    0 => otherwise.clone()?,
+                    _ => then.clone()?,
+                };
+                // only the arm taken has to be a constant
+                match (then, otherwise) {
+                    (Some((_, then)), Some((_, otherwise))) => {
+                        Some((value, Type::common(&then, &otherwise)))
+                    }
+                    _ => Some((value, ty)),
+                }
+            }
+            _ => None,
+        }
     }
 }
 
@@ -153,6 +650,11 @@ pub struct Function {
     pub exprs: Vec<Expr>,
     pub name: String,
     pub args: Vec<Arg>,
+    pub prototype: bool,
+    // `static` keeps the name private to its file
+    pub is_static: bool,
+    // the signature, from the return type to the closing parenthesis
+    pub span: Span,
 }
 
 impl Deparse for Function {
@@ -163,6 +665,16 @@ impl Deparse for Function {
             .map(|a| a.deparse())
             .collect::<Vec<_>>()
             .join(", ");
+        let storage = if self.is_static { "static " } else { "" };
+        if self.prototype {
+            return format!(
+                "{}{} {}({});",
+                storage,
+                self.ret_type.deparse(),
+                self.name,
+                args
+            );
+        }
         let exprs = self
             .exprs
             .iter()
@@ -170,7 +682,8 @@ impl Deparse for Function {
             .collect::<Vec<_>>()
             .join(";\n");
         format!(
-            "{} {}({}) {{\n{} ;\n}}",
+            "{}{} {}({}) {{\n{} ;\n}}",
+            storage,
             self.ret_type.deparse(),
             self.name,
             args,
@@ -179,19 +692,23 @@ impl Deparse for Function {
     }
 }
 
-impl<'a> Arbitrary for Function {
+impl Arbitrary for Function {
     fn arbitrary(g: &mut Gen) -> Self {
         let ret_type = Type::Void;
         let name = readable_string(g);
         let mut exprs = Vec::new();
         for _ in 0..g.size() {
-            exprs.push(Expr::arbitrary(g));
+            exprs.push(Expr::ExprStmt {
+                expr: Box::new(Expr::arbitrary(g)),
+                span: Span::default(),
+            });
         }
         let mut args = Vec::new();
         for _ in 0..g.size() {
             args.push(Arg {
                 ty: Type::Int,
                 name: readable_string(g),
+                span: Span::default(),
             });
         }
         Function {
@@ -199,20 +716,76 @@ impl<'a> Arbitrary for Function {
             exprs,
             name,
             args,
+            prototype: false,
+            is_static: false,
+            span: Span::default(),
+        }
+    }
+}
+
+/// A variable at file scope, which lives as long as the program does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Global {
+    pub ty: Type,
+    pub name: String,
+    pub init: Option<Expr>,
+    // `static` keeps the name private to its file
+    pub is_static: bool,
+    pub span: Span,
+}
+
+impl Deparse for Global {
+    fn deparse(&self) -> String {
+        let storage = if self.is_static { "static " } else { "" };
+        match &self.init {
+            Some(init) => format!(
+                "{}{} = {};",
+                storage,
+                self.ty.declarator(&self.name),
+                init.deparse()
+            ),
+            None => format!("{}{};", storage, self.ty.declarator(&self.name)),
         }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Program {
+    pub globals: Vec<Global>,
     pub functions: Vec<Function>,
 }
 
+/// A declaration at file scope, as met in the source.
+#[derive(Clone, Copy, Debug)]
+pub enum Item<'a> {
+    Global(&'a Global),
+    Function(&'a Function),
+}
+
+impl Program {
+    /// Globals and functions in source order, each only sees the names
+    /// declared before it.
+    pub fn items(&self) -> impl Iterator<Item = Item<'_>> {
+        let mut globals = self.globals.iter().peekable();
+        let mut functions = self.functions.iter().peekable();
+        std::iter::from_fn(move || match (globals.peek(), functions.peek()) {
+            (Some(global), Some(func)) if global.span.start < func.span.start => {
+                globals.next().map(Item::Global)
+            }
+            (_, Some(_)) => functions.next().map(Item::Function),
+            (Some(_), None) => globals.next().map(Item::Global),
+            (None, None) => None,
+        })
+    }
+}
+
 impl Deparse for Program {
     fn deparse(&self) -> String {
-        self.functions
-            .iter()
-            .map(|f| f.deparse())
+        self.items()
+            .map(|item| match item {
+                Item::Global(global) => global.deparse(),
+                Item::Function(func) => func.deparse(),
+            })
             .collect::<Vec<_>>()
             .join("\n")
     }
@@ -220,11 +793,55 @@ impl Deparse for Program {
 
 impl Arbitrary for Program {
     fn arbitrary(g: &mut Gen) -> Self {
-        let mut functions = Vec::new();
+        let mut functions: Vec<Function> = Vec::new();
         for _ in 0..g.size() {
-            functions.push(Function::arbitrary(g));
+            let function = Function::arbitrary(g);
+            if functions.iter().all(|f| f.name != function.name) {
+                functions.push(function);
+            }
+        }
+        Program {
+            globals: Vec::new(),
+            functions,
+        }
+    }
+}
+
+// failures seen during one parse, shared by all of its states
+#[derive(Debug, Default)]
+pub struct Tracker {
+    furthest: RefCell<Option<ParseError>>,
+    errors: RefCell<Vec<ParseError>>,
+}
+
+impl Tracker {
+    // keep the failure that got furthest, merging those that got equally far
+    fn record(&self, err: &ParseError) {
+        let mut furthest = self.furthest.borrow_mut();
+        match &mut *furthest {
+            Some(best) if best.span.start > err.span.start => {}
+            Some(best) if best.span.start == err.span.start => {
+                for name in &err.expected {
+                    if !best.expected.contains(name) {
+                        best.expected.push(name.clone());
+                    }
+                }
+            }
+            _ => *furthest = Some(err.clone()),
+        }
+    }
+
+    fn take_furthest(&self) -> Option<ParseError> {
+        self.furthest.borrow_mut().take()
+    }
+
+    fn report(&self, err: ParseError) {
+        let mut errors = self.errors.borrow_mut();
+        // a failure at the end of input is met again by every enclosing block
+        let seen = errors.last().is_some_and(|last| *last == err);
+        if !seen {
+            errors.push(err);
         }
-        Program { functions }
     }
 }
 
@@ -232,12 +849,25 @@ impl Arbitrary for Program {
 pub struct State<'a> {
     pub code: &'a str,
     pub index: usize,
+    pub tracker: &'a Tracker,
 }
 
 impl<'a> State<'a> {
+    pub fn new(code: &'a str, tracker: &'a Tracker) -> State<'a> {
+        State {
+            code,
+            index: 0,
+            tracker,
+        }
+    }
+
     fn rest(&self) -> Option<&'a str> {
         self.code.get(self.index..)
     }
+
+    fn at_end(&self) -> bool {
+        self.rest().is_none_or(str::is_empty)
+    }
 }
 
 pub fn head(state: State) -> Option<char> {
@@ -250,28 +880,78 @@ pub fn tail(state: State) -> State {
         None => 0,
     };
     State {
-        code: state.code,
         index: state.index + add,
+        ..state
     }
 }
 
-pub type Answer<'a, A> = Result<(State<'a>, A), (String, Backtrace)>;
-pub type Parser<'a, A> = Box<dyn Fn(State<'a>) -> Answer<'a, A>>;
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    // everything that would have been accepted at `span`
+    pub expected: Vec<String>,
+    pub span: Span,
+}
 
-pub fn print_state(state: State) -> &str {
-    state.code.get(state.index..).unwrap_or("")
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self
+            .expected
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>();
+        match names.split_last() {
+            Some((last, [])) => write!(f, "error: expected {}", last),
+            Some((last, rest)) => write!(f, "error: expected {} or {}", rest.join(", "), last),
+            None => write!(f, "error: syntax error"),
+        }
+    }
 }
 
+pub type Answer<'a, A> = Result<(State<'a>, A), ParseError>;
+pub type Parser<'a, A> = Box<dyn Fn(State<'a>) -> Answer<'a, A>>;
+
 pub fn expected<'a, A>(state: State<'a>, name: &str, size: usize) -> Answer<'a, A> {
-    let end = state.index + size;
-    let err = state.code.get(state.index..end).unwrap_or("");
-    let rest = state.code.get(end..).unwrap_or("");
-    // Err(format!(
-    //     "Expected `{}`:\n\x1b[31m\x1b[01m{}\x1b[0m{}",
-    //     name, err, rest
-    // ))
-    let bt = Backtrace::new();
-    Err((format!("Expected `{}`: {}{}", name, err, rest), bt))
+    let end = (state.index + size).min(state.code.len());
+    let err = ParseError {
+        expected: vec![name.to_string()],
+        span: Span::new(state.index, end),
+    };
+    state.tracker.record(&err);
+    Err(err)
+}
+
+// a failure that is not recorded, for an alternative that does not apply
+// rather than one that went wrong
+fn unexpected<A>(state: State) -> Answer<A> {
+    Err(ParseError {
+        expected: Vec::new(),
+        span: Span::new(state.index, state.index),
+    })
+}
+
+// the `,` between the items of a list that has not reached its `close`,
+// so none may trail the last one
+fn separator<'a>(state: State<'a>, close: &str) -> Answer<'a, ()> {
+    let (state, matched) = text(state, ",")?;
+    if matched {
+        return Ok((state, ()));
+    }
+    let err = ParseError {
+        expected: vec![close.to_string(), ",".to_string()],
+        span: Span::new(state.index, (state.index + 1).min(state.code.len())),
+    };
+    state.tracker.record(&err);
+    Err(err)
+}
+
+// the position of the next token
+fn start(state: State) -> Answer<usize> {
+    let (state, _) = skip(state)?;
+    Ok((state, state.index))
+}
+
+fn span_from(start: usize, state: State) -> Span {
+    Span::new(start, state.index)
 }
 
 pub fn skip_comment(mut state: State) -> Answer<bool> {
@@ -328,8 +1008,8 @@ pub fn text<'a>(state: State<'a>, pat: &str) -> Answer<'a, bool> {
     if let Some(rest) = state.rest() {
         if rest.starts_with(pat) {
             let state = State {
-                code: state.code,
                 index: state.index + pat.len(),
+                ..state
             };
             return Ok((state, true));
         }
@@ -364,11 +1044,23 @@ pub fn grammar<'a, A: 'a>(
     choices: &[Parser<'a, Option<A>>],
     state: State<'a>,
 ) -> Answer<'a, A> {
-    let (state, result) = optional_grammar(choices, state)?;
-    if let Some(result) = result {
-        Ok((state, result))
-    } else {
-        expected(state, name, 1)
+    let (state, _) = skip(state)?;
+    let outer = state.tracker.take_furthest();
+    let answer = optional_grammar(choices, state);
+    let inner = state.tracker.take_furthest();
+    if let Some(err) = outer {
+        state.tracker.record(&err);
+    }
+    if let Some(err) = inner {
+        // alternatives that fail right where the rule starts are summed up
+        // by its name, deeper failures are more specific and are kept
+        if err.span.start > state.index || !matches!(answer, Ok((_, None))) {
+            state.tracker.record(&err);
+        }
+    }
+    match answer? {
+        (state, Some(result)) => Ok((state, result)),
+        (_, None) => expected(state, name, 1),
     }
 }
 
@@ -377,23 +1069,119 @@ fn enum_consumer<'a, A>(state: State<'a>, pat: &'static str, val: A) -> Answer<'
     Ok((state, if matched { Some(val) } else { None }))
 }
 
+// like `text`, but only a whole word, so `int` does not match `integer`
+fn keyword<'a>(state: State<'a>, word: &str) -> Answer<'a, bool> {
+    let (new_state, matched) = text(state, word)?;
+    if matched && !head(new_state).is_some_and(is_letter) {
+        return Ok((new_state, true));
+    }
+    let (state, _) = skip(state)?;
+    Ok((state, false))
+}
+
+// like `consume`, but only a whole word
+fn consume_keyword<'a>(state: State<'a>, word: &'a str) -> Answer<'a, &'a str> {
+    let (state, matched) = keyword(state, word)?;
+    if matched {
+        Ok((state, word))
+    } else {
+        expected(state, word, word.len())
+    }
+}
+
+fn keyword_consumer<'a, A>(state: State<'a>, word: &'static str, val: A) -> Answer<'a, Option<A>> {
+    let (state, matched) = keyword(state, word)?;
+    Ok((state, if matched { Some(val) } else { None }))
+}
+
+// any number of `volatile` and `const`, each wrapping `ty`
+fn qualifiers(state: State, ty: Type) -> Answer<Type> {
+    let (state, is_volatile) = keyword(state, "volatile")?;
+    if is_volatile {
+        return qualifiers(state, Type::Volatile(Box::new(ty)));
+    }
+    let (state, is_const) = keyword(state, "const")?;
+    if is_const {
+        return qualifiers(state, Type::Const(Box::new(ty)));
+    }
+    Ok((state, ty))
+}
+
+// a qualifier applies to what is to the left of it, or the base type when
+// it comes first: `volatile int *` and `int volatile *` point to a volatile
+// int, `int * volatile` is a volatile pointer
 fn parse_type(state: State) -> Answer<Type> {
-    grammar(
-        "type",
-        &[
-            Box::new(|state| enum_consumer(state, "int", Type::Int)),
-            Box::new(|state| enum_consumer(state, "void", Type::Void)),
-        ],
-        state,
-    )
+    let (state, leading) = qualifiers(state, Type::Void)?;
+    let (state, base) = parse_base_type(state)?;
+    let (mut state, mut ty) = qualifiers(state, requalify(&leading, base))?;
+    loop {
+        let (new_state, is_pointer) = text(state, "*")?;
+        if !is_pointer {
+            return Ok((state, ty));
+        }
+        (state, ty) = qualifiers(new_state, Type::Pointer(Box::new(ty)))?;
+    }
 }
 
-fn is_letter(chr: char) -> bool {
-    chr.is_ascii_alphanumeric() || chr == '_'
+// move the qualifiers parsed around a placeholder onto `base`
+fn requalify(qualified: &Type, base: Type) -> Type {
+    match qualified {
+        Type::Volatile(ty) => Type::Volatile(Box::new(requalify(ty, base))),
+        Type::Const(ty) => Type::Const(Box::new(requalify(ty, base))),
+        _ => base,
+    }
+}
+
+// `unsigned` and `signed` go with `char`, `short`, `int` and `long`, or on
+// their own mean `int`; the `<stdint.h>` names are built in
+fn parse_base_type(state: State) -> Answer<Type> {
+    let (state, unsigned) = keyword(state, "unsigned")?;
+    let (state, signed) = if unsigned {
+        (state, false)
+    } else {
+        keyword(state, "signed")?
+    };
+    let sized = || -> [Parser<Option<Type>>; 4] {
+        [
+            Box::new(|state| keyword_consumer(state, "char", Type::Char)),
+            Box::new(|state| keyword_consumer(state, "short", Type::Short)),
+            Box::new(|state| keyword_consumer(state, "long", Type::Long)),
+            Box::new(|state| keyword_consumer(state, "int", Type::Int)),
+        ]
+    };
+    let (state, ty) = if unsigned || signed {
+        let (state, ty) = optional_grammar(&sized(), state)?;
+        (state, ty.unwrap_or(Type::Int))
+    } else {
+        let [char, short, long, int] = sized();
+        grammar(
+            "type",
+            &[
+                char,
+                short,
+                long,
+                int,
+                Box::new(|state| keyword_consumer(state, "void", Type::Void)),
+                Box::new(|state| keyword_consumer(state, "int8_t", Type::Char)),
+                Box::new(|state| keyword_consumer(state, "int16_t", Type::Short)),
+                Box::new(|state| keyword_consumer(state, "int32_t", Type::Int)),
+                Box::new(|state| keyword_consumer(state, "uint8_t", Type::UChar)),
+                Box::new(|state| keyword_consumer(state, "uint16_t", Type::UShort)),
+                Box::new(|state| keyword_consumer(state, "uint32_t", Type::UInt)),
+            ],
+            state,
+        )?
+    };
+    // `short int` and `long int`
+    let (state, _) = match ty {
+        Type::Short | Type::Long => keyword(state, "int")?,
+        _ => (state, false),
+    };
+    Ok((state, if unsigned { ty.to_unsigned() } else { ty }))
 }
 
-fn is_number(chr: char) -> bool {
-    chr.is_numeric()
+fn is_letter(chr: char) -> bool {
+    chr.is_ascii_alphanumeric() || chr == '_'
 }
 
 pub fn name(state: State) -> Answer<String> {
@@ -410,11 +1198,12 @@ pub fn name(state: State) -> Answer<String> {
     Ok((state, name))
 }
 
-pub fn int_here(state: State) -> Answer<String> {
+// the digits of a number in base `radix`
+pub fn int_here(state: State, radix: u32) -> Answer<String> {
     let mut name: String = String::new();
     let (mut state, _) = skip(state)?;
     while let Some(got) = head(state) {
-        if is_number(got) {
+        if got.is_digit(radix) {
             name.push(got);
             state = tail(state);
         } else {
@@ -425,208 +1214,404 @@ pub fn int_here(state: State) -> Answer<String> {
 }
 
 fn parse_int(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
     let (state, is_hex) = text(state, "0x")?;
     if is_hex {
-        let (state, src) = int_here(state)?;
+        let (state, src) = int_here(state, 16)?;
         match u32::from_str_radix(&src, 16) {
-            Ok(value) => Ok((state, Expr::Int { value })),
+            Ok(value) => Ok((
+                state,
+                Expr::Int {
+                    value,
+                    span: span_from(begin, state),
+                },
+            )),
             Err(_) => expected(state, "hexadecimal number", src.len()),
         }
     } else {
-        let (state, src) = int_here(state)?;
-        match u32::from_str_radix(&src, 10) {
-            Ok(value) => Ok((state, Expr::Int { value })),
+        let (state, src) = int_here(state, 10)?;
+        match src.parse::<u32>() {
+            Ok(value) => Ok((
+                state,
+                Expr::Int {
+                    value,
+                    span: span_from(begin, state),
+                },
+            )),
             Err(_) => expected(state, "base10 number", src.len()),
         }
     }
 }
 
 fn parse_var(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
     let (state, name) = name(state)?;
-    Ok((state, Expr::Var { name }))
+    if name.is_empty() {
+        return expected(state, "identifier", 1);
+    }
+    let span = span_from(begin, state);
+    Ok((state, Expr::Var { name, span }))
 }
 
 fn parse_deref(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
     let (state, _) = consume(state, "*")?;
     let (state, expr) = parse_factor(state)?;
     Ok((
         state,
         Expr::Deref {
             addr: Box::new(expr),
+            span: span_from(begin, state),
         },
     ))
 }
 
+fn parse_arglist<'a, 'b>(
+    args: &'b mut Vec<Expr>,
+    pat: &str,
+    state: State<'a>,
+) -> Answer<'a, &'b Vec<Expr>> {
+    let (state, matched) = text(state, pat)?;
+    if matched {
+        return Ok((state, args));
+    }
+    let state = if args.is_empty() {
+        state
+    } else {
+        separator(state, pat)?.0
+    };
+    let (state, expr) = parse_expr(state)?;
+    args.push(expr);
+    parse_arglist(args, pat, state)
+}
+
+fn parse_call(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, name) = name(state)?;
+    if name.is_empty() {
+        return expected(state, "function name", 1);
+    }
+    let (state, _) = consume(state, "(")?;
+    let mut args = Vec::new();
+    let (state, _) = parse_arglist(&mut args, ")", state)?;
+    let span = span_from(begin, state);
+    Ok((state, Expr::Call { name, args, span }))
+}
+
 fn parse_primary_expr(state: State) -> Answer<Expr> {
     grammar(
-        "primary",
+        "expression",
         &[
             Box::new(|state| try_parser(parse_deref, state)),
             Box::new(|state| try_parser(parse_int, state)),
+            Box::new(|state| try_parser(parse_call, state)),
             Box::new(|state| try_parser(parse_var, state)),
         ],
         state,
     )
 }
 
-fn parse_factor(state: State) -> Answer<Expr> {
+// a parenthesized or primary expression, indexed any number of times
+fn parse_postfix_expr(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
     let (state, is_paren) = text(state, "(")?;
-    if is_paren {
+    let (mut state, mut expr) = if is_paren {
         let (state, expr) = parse_expr(state)?;
         let (state, _) = consume(state, ")")?;
-        Ok((state, expr))
+        (state, expr)
     } else {
-        parse_primary_expr(state)
+        parse_primary_expr(state)?
+    };
+    loop {
+        let (new_state, is_index) = text(state, "[")?;
+        if !is_index {
+            return Ok((state, expr));
+        }
+        let (new_state, index) = parse_expr(new_state)?;
+        let (new_state, _) = consume(new_state, "]")?;
+        state = new_state;
+        expr = Expr::Index {
+            base: Box::new(expr),
+            index: Box::new(index),
+            span: span_from(begin, state),
+        };
     }
 }
 
-fn parse_term(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_factor(state)?;
-    let (state, op_option) = optional_grammar(
+// `--` has to be tried before `-`
+fn parse_inc_dec(state: State) -> Answer<Option<Op>> {
+    optional_grammar(
         &[
-            Box::new(|state| enum_consumer(state, "/", Op::Div)),
-            Box::new(|state| enum_consumer(state, "*", Op::Mul)),
+            Box::new(|state| enum_consumer(state, "++", Op::Add)),
+            Box::new(|state| enum_consumer(state, "--", Op::Sub)),
         ],
         state,
-    )?;
-    if let Some(op) = op_option {
-        let (state, rhs) = parse_term(state)?;
+    )
+}
+
+fn parse_factor(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, inc_dec) = parse_inc_dec(state)?;
+    if let Some(op) = inc_dec {
+        let (state, expr) = parse_factor(state)?;
         return Ok((
             state,
-            Expr::BinOp {
+            Expr::IncDec {
                 op,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
+                prefix: true,
+                expr: Box::new(expr),
+                span: span_from(begin, state),
             },
         ));
     }
-    Ok((state, lhs))
-}
-
-fn parse_additive_expr(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_term(state)?;
     let (state, op_option) = optional_grammar(
         &[
-            Box::new(|state| enum_consumer(state, "-", Op::Sub)),
-            Box::new(|state| enum_consumer(state, "+", Op::Add)),
+            Box::new(|state| enum_consumer(state, "-", UnaryOp::Neg)),
+            Box::new(|state| enum_consumer(state, "!", UnaryOp::Not)),
+            Box::new(|state| enum_consumer(state, "~", UnaryOp::BitNot)),
+            Box::new(|state| enum_consumer(state, "&", UnaryOp::AddrOf)),
         ],
         state,
     )?;
     if let Some(op) = op_option {
-        let (state, rhs) = parse_additive_expr(state)?;
+        let (state, expr) = parse_factor(state)?;
         return Ok((
             state,
-            Expr::BinOp {
+            Expr::Unary {
                 op,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
+                expr: Box::new(expr),
+                span: span_from(begin, state),
             },
         ));
     }
-    Ok((state, lhs))
+    let (state, expr) = parse_postfix_expr(state)?;
+    let (state, inc_dec) = parse_inc_dec(state)?;
+    match inc_dec {
+        Some(op) => Ok((
+            state,
+            Expr::IncDec {
+                op,
+                prefix: false,
+                expr: Box::new(expr),
+                span: span_from(begin, state),
+            },
+        )),
+        None => Ok((state, expr)),
+    }
+}
+
+fn binop(op: Op, lhs: Expr, rhs: Expr) -> Expr {
+    let span = lhs.span().to(rhs.span());
+    Expr::BinOp {
+        op,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+        span,
+    }
+}
+
+// like `enum_consumer`, but not when the operator starts a longer one
+fn op_consumer<'a, A>(
+    state: State<'a>,
+    pat: &'static str,
+    longer: &[&str],
+    val: A,
+) -> Answer<'a, Option<A>> {
+    for longer in longer {
+        let (_, matched) = text(state, longer)?;
+        if matched {
+            return Ok((state, None));
+        }
+    }
+    enum_consumer(state, pat, val)
+}
+
+// one precedence level of left associative binary operators
+fn parse_binary<'a>(
+    state: State<'a>,
+    operand: fn(State) -> Answer<Expr>,
+    ops: &[Parser<'a, Option<Op>>],
+) -> Answer<'a, Expr> {
+    let (mut state, mut lhs) = operand(state)?;
+    loop {
+        let (new_state, op_option) = optional_grammar(ops, state)?;
+        let op = match op_option {
+            Some(op) => op,
+            None => return Ok((state, lhs)),
+        };
+        let (new_state, rhs) = operand(new_state)?;
+        lhs = binop(op, lhs, rhs);
+        state = new_state;
+    }
+}
+
+fn parse_term(state: State) -> Answer<Expr> {
+    parse_binary(
+        state,
+        parse_factor,
+        &[
+            Box::new(|state| enum_consumer(state, "/", Op::Div)),
+            Box::new(|state| enum_consumer(state, "*", Op::Mul)),
+            Box::new(|state| enum_consumer(state, "%", Op::Rem)),
+        ],
+    )
+}
+
+fn parse_additive_expr(state: State) -> Answer<Expr> {
+    parse_binary(
+        state,
+        parse_term,
+        &[
+            Box::new(|state| enum_consumer(state, "-", Op::Sub)),
+            Box::new(|state| enum_consumer(state, "+", Op::Add)),
+        ],
+    )
+}
+
+fn parse_shift_expr(state: State) -> Answer<Expr> {
+    parse_binary(
+        state,
+        parse_additive_expr,
+        &[
+            Box::new(|state| enum_consumer(state, "<<", Op::Shl)),
+            Box::new(|state| enum_consumer(state, ">>", Op::Shr)),
+        ],
+    )
 }
 
 fn parse_relational_expr(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_additive_expr(state)?;
-    let (state, op_option) = optional_grammar(
+    parse_binary(
+        state,
+        parse_shift_expr,
         &[
             Box::new(|state| enum_consumer(state, "<=", Op::Le)),
             Box::new(|state| enum_consumer(state, ">=", Op::Ge)),
             Box::new(|state| enum_consumer(state, "<", Op::Lt)),
             Box::new(|state| enum_consumer(state, ">", Op::Gt)),
         ],
-        state,
-    )?;
-    if let Some(op) = op_option {
-        let (state, rhs) = parse_relational_expr(state)?;
-        return Ok((
-            state,
-            Expr::BinOp {
-                op,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
-            },
-        ));
-    }
-    Ok((state, lhs))
+    )
 }
 
 fn parse_equality_expr(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_relational_expr(state)?;
-    let (state, op_option) = optional_grammar(
+    parse_binary(
+        state,
+        parse_relational_expr,
         &[
             Box::new(|state| enum_consumer(state, "==", Op::Eq)),
             Box::new(|state| enum_consumer(state, "!=", Op::Ne)),
         ],
+    )
+}
+
+fn parse_bitwise_and_expr(state: State) -> Answer<Expr> {
+    parse_binary(
         state,
-    )?;
-    if let Some(op) = op_option {
-        let (state, rhs) = parse_equality_expr(state)?;
-        return Ok((
-            state,
-            Expr::BinOp {
-                op,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
-            },
-        ));
-    }
-    Ok((state, lhs))
+        parse_equality_expr,
+        &[Box::new(|state| {
+            op_consumer(state, "&", &["&&"], Op::BitAnd)
+        })],
+    )
+}
+
+fn parse_bitwise_xor_expr(state: State) -> Answer<Expr> {
+    parse_binary(
+        state,
+        parse_bitwise_and_expr,
+        &[Box::new(|state| enum_consumer(state, "^", Op::BitXor))],
+    )
+}
+
+fn parse_bitwise_or_expr(state: State) -> Answer<Expr> {
+    parse_binary(
+        state,
+        parse_bitwise_xor_expr,
+        &[Box::new(|state| {
+            op_consumer(state, "|", &["||"], Op::BitOr)
+        })],
+    )
 }
 
 fn parse_logical_and_expr(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_equality_expr(state)?;
-    let (state, and_op) = text(state, "&&")?;
-    if and_op {
-        let (state, rhs) = parse_logical_and_expr(state)?;
-        return Ok((
-            state,
-            Expr::BinOp {
-                op: Op::And,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
-            },
-        ));
-    }
-    Ok((state, lhs))
+    parse_binary(
+        state,
+        parse_bitwise_or_expr,
+        &[Box::new(|state| enum_consumer(state, "&&", Op::And))],
+    )
 }
 
 fn parse_logical_or_expr(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_logical_and_expr(state)?;
-    let (state, or_op) = text(state, "||")?;
-    if or_op {
-        let (state, rhs) = parse_logical_or_expr(state)?;
-        return Ok((
-            state,
-            Expr::BinOp {
-                op: Op::Or,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
-            },
-        ));
-    }
-    Ok((state, lhs))
+    parse_binary(
+        state,
+        parse_logical_and_expr,
+        &[Box::new(|state| enum_consumer(state, "||", Op::Or))],
+    )
 }
 
-fn parse_assignment_expr(state: State) -> Answer<Expr> {
-    let (state, lhs) = parse_primary_expr(state)?;
-    let (state, _) = consume(state, "=")?;
-    let (state, rhs) = parse_logical_or_expr(state)?;
+// right associative, `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+fn parse_conditional_expr(state: State) -> Answer<Expr> {
+    let (state, cond) = parse_logical_or_expr(state)?;
+    let (state, is_conditional) = text(state, "?")?;
+    if !is_conditional {
+        return Ok((state, cond));
+    }
+    let (state, then) = parse_expr(state)?;
+    let (state, _) = consume(state, ":")?;
+    let (state, otherwise) = parse_conditional_expr(state)?;
+    let span = cond.span().to(otherwise.span());
     Ok((
         state,
-        Expr::Assign {
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
+        Expr::Conditional {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+            span,
         },
     ))
 }
 
+fn parse_assignment_expr(state: State) -> Answer<Expr> {
+    let (state, lhs) = parse_postfix_expr(state)?;
+    let (state, op_option) = optional_grammar(
+        &[
+            Box::new(|state| enum_consumer(state, "+=", Op::Add)),
+            Box::new(|state| enum_consumer(state, "-=", Op::Sub)),
+            Box::new(|state| enum_consumer(state, "*=", Op::Mul)),
+            Box::new(|state| enum_consumer(state, "/=", Op::Div)),
+            Box::new(|state| enum_consumer(state, "%=", Op::Rem)),
+            Box::new(|state| enum_consumer(state, "&=", Op::BitAnd)),
+            Box::new(|state| enum_consumer(state, "|=", Op::BitOr)),
+            Box::new(|state| enum_consumer(state, "^=", Op::BitXor)),
+            Box::new(|state| enum_consumer(state, "<<=", Op::Shl)),
+            Box::new(|state| enum_consumer(state, ">>=", Op::Shr)),
+        ],
+        state,
+    )?;
+    let state = match op_option {
+        Some(_) => state,
+        None => match op_consumer(state, "=", &["=="], ())? {
+            (state, Some(())) => state,
+            // like a missing binary operator, this is for whatever follows
+            // the expression to report
+            (state, None) => return unexpected(state),
+        },
+    };
+    let (state, rhs) = parse_conditional_expr(state)?;
+    let span = lhs.span().to(rhs.span());
+    let lhs = Box::new(lhs);
+    let rhs = Box::new(rhs);
+    let expr = match op_option {
+        Some(op) => Expr::CompoundAssign { lhs, rhs, op, span },
+        None => Expr::Assign { lhs, rhs, span },
+    };
+    Ok((state, expr))
+}
+
 fn parse_expr(state: State) -> Answer<Expr> {
     grammar(
-        "expr",
+        "expression",
         &[
             Box::new(|state| try_parser(parse_assignment_expr, state)),
-            Box::new(|state| try_parser(parse_logical_or_expr, state)),
+            Box::new(|state| try_parser(parse_conditional_expr, state)),
         ],
         state,
     )
@@ -640,10 +1625,47 @@ fn parse_compound_statement(state: State) -> Answer<Vec<Expr>> {
         if got {
             return Ok((new_state, exprs));
         }
-        let (state2, expr) = parse_statement(new_state)?;
-        exprs.push(expr);
-        state = state2;
+        if new_state.at_end() {
+            return expected(new_state, "}", 0);
+        }
+        state.tracker.take_furthest();
+        state = match parse_statement(new_state) {
+            Ok((new_state, expr)) => {
+                exprs.push(expr);
+                new_state
+            }
+            Err(err) => recover(new_state, err),
+        };
+    }
+}
+
+// report a failed statement or function and skip past it: up to and
+// including the next `;`, or up to the `}` closing the enclosing block,
+// stepping over any nested blocks on the way
+fn recover(state: State, err: ParseError) -> State {
+    let err = state.tracker.take_furthest().unwrap_or(err);
+    let mut state = State {
+        index: err.span.start.max(state.index),
+        ..state
+    };
+    state.tracker.report(err);
+    let mut depth = 0;
+    while let Some(c) = head(state) {
+        match c {
+            ';' if depth == 0 => return tail(state),
+            '}' if depth == 0 => return state,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return tail(state);
+                }
+            }
+            _ => {}
+        }
+        state = tail(state);
     }
+    state
 }
 
 fn try_parser<'a>(parser: fn(State) -> Answer<Expr>, state: State<'a>) -> Answer<'a, Option<Expr>> {
@@ -653,94 +1675,188 @@ fn try_parser<'a>(parser: fn(State) -> Answer<Expr>, state: State<'a>) -> Answer
     }
 }
 
-fn parse_declaration_statement(state: State) -> Answer<Expr> {
+// the `[n]` after a declared name, `[]` is `None`
+fn parse_array_sizes(state: State) -> Answer<Vec<Option<u32>>> {
+    let mut sizes = Vec::new();
+    let mut state = state;
+    loop {
+        let (new_state, is_array) = text(state, "[")?;
+        if !is_array {
+            return Ok((state, sizes));
+        }
+        let (new_state, is_unsized) = text(new_state, "]")?;
+        if is_unsized {
+            sizes.push(None);
+            state = new_state;
+            continue;
+        }
+        let (new_state, size) = parse_int(new_state)?;
+        let (new_state, _) = consume(new_state, "]")?;
+        match size {
+            Expr::Int { value, .. } if value > 0 => sizes.push(Some(value)),
+            _ => return expected(new_state, "array size", 1),
+        }
+        state = new_state;
+    }
+}
+
+// the array type of `ty name[sizes]`, only the outermost size may be left
+// to `outer`
+fn array_type<'a>(
+    state: State<'a>,
+    ty: Type,
+    sizes: &[Option<u32>],
+    outer: Option<u32>,
+) -> Answer<'a, Type> {
+    let mut ty = ty;
+    for (i, size) in sizes.iter().enumerate().rev() {
+        let len = match (size, i) {
+            (Some(len), _) => *len,
+            (None, 0) => match outer {
+                Some(len) => len,
+                None => return expected(state, "array size", 1),
+            },
+            (None, _) => return expected(state, "array size", 1),
+        };
+        ty = Type::Array(Box::new(ty), len);
+    }
+    Ok((state, ty))
+}
+
+// an expression, or a braced list of initializers for an array
+fn parse_initializer(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (mut state, is_list) = text(state, "{")?;
+    if !is_list {
+        return parse_expr(state);
+    }
+    let mut values = Vec::new();
+    // a trailing comma is allowed
+    loop {
+        let (new_state, is_end) = text(state, "}")?;
+        if is_end {
+            state = new_state;
+            break;
+        }
+        let (new_state, value) = parse_initializer(new_state)?;
+        values.push(value);
+        let (new_state, is_more) = text(new_state, ",")?;
+        if !is_more {
+            (state, _) = consume(new_state, "}")?;
+            break;
+        }
+        state = new_state;
+    }
+    let span = span_from(begin, state);
+    Ok((state, Expr::InitList { values, span }))
+}
+
+// the type, name and initializer of a variable, up to and with the `;`
+fn parse_declaration(state: State) -> Answer<(Type, String, Option<Expr>)> {
     let (state, ty) = parse_type(state)?;
     let (state, identifier) = name(state)?;
+    let (state, sizes) = parse_array_sizes(state)?;
     let (state, has_init) = text(state, "=")?;
     if !has_init {
+        let (state, ty) = array_type(state, ty, &sizes, None)?;
         let (state, _) = consume(state, ";")?;
-        return Ok((
-            state,
-            Expr::Decl {
-                ty,
-                name: identifier,
-                init: None,
-            },
-        ));
+        return Ok((state, (ty, identifier, None)));
     }
-    let (state, expr) = parse_expr(state)?;
+    let (state, expr) = parse_initializer(state)?;
+    // `int a[] = {1, 2}` has as many elements as it is given
+    let outer = match &expr {
+        Expr::InitList { values, .. } => Some(values.len() as u32),
+        _ => None,
+    };
+    let (state, ty) = array_type(state, ty, &sizes, outer)?;
     let (state, _) = consume(state, ";")?;
+    Ok((state, (ty, identifier, Some(expr))))
+}
+
+fn parse_declaration_statement(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, (ty, name, init)) = parse_declaration(state)?;
     Ok((
         state,
         Expr::Decl {
             ty,
-            name: identifier,
-            init: Some(Box::new(expr)),
+            name,
+            init: init.map(Box::new),
+            span: span_from(begin, state),
         },
     ))
 }
 
+// a braced block or a single statement, as taken by `if`, `else`, `for`
+// and `do`
+fn parse_body(state: State) -> Answer<Vec<Expr>> {
+    let (_, is_compound) = text(state, "{")?;
+    if is_compound {
+        return parse_compound_statement(state);
+    }
+    let (state, expr) = parse_statement(state)?;
+    Ok((state, vec![expr]))
+}
+
 fn parse_selection_statement(state: State) -> Answer<Expr> {
-    let (state, _) = consume(state, "if")?;
+    let (state, begin) = start(state)?;
+    let (state, _) = consume_keyword(state, "if")?;
     let (state, _) = consume(state, "(")?;
     let (state, expr) = parse_expr(state)?;
     let (state, _) = consume(state, ")")?;
-    let (state, then) = grammar(
-        "body of if",
-        &[
-            Box::new(|state| {
-                let (state, body) = parse_compound_statement(state)?;
-                Ok((state, Some(body)))
-            }),
-            Box::new(|state| {
-                let (state, expr) = parse_statement(state)?;
-                Ok((state, Some(vec![expr])))
-            }),
-        ],
-        state,
-    )?;
-    let (state, else_option) = optional_grammar(
-        &[
-            Box::new(|state| {
-                let (state, _) = consume(state, "else")?;
-                let (state, body) = parse_compound_statement(state)?;
-                Ok((state, Some(body)))
-            }),
-            Box::new(|state| {
-                let (state, _) = consume(state, "else")?;
-                let (state, expr) = parse_statement(state)?;
-                Ok((state, Some(vec![expr])))
-            }),
-        ],
-        state,
-    )?;
-    return Ok((
+    let (state, then) = parse_body(state)?;
+    let (state, has_else) = keyword(state, "else")?;
+    let (state, otherwise) = if has_else {
+        parse_body(state)?
+    } else {
+        (state, vec![])
+    };
+    Ok((
         state,
         Expr::If {
             cond: Box::new(expr),
             then,
-            otherwise: else_option.unwrap_or(vec![]),
+            otherwise,
+            span: span_from(begin, state),
         },
-    ));
+    ))
 }
 
 fn parse_return_statement(state: State) -> Answer<Expr> {
-    let (state, ret) = text(state, "return")?;
+    let (state, begin) = start(state)?;
+    let (state, _) = consume_keyword(state, "return")?;
+    let (state, bare) = text(state, ";")?;
+    if bare {
+        let span = span_from(begin, state);
+        return Ok((state, Expr::Return { expr: None, span }));
+    }
     let (state, expr) = parse_expr(state)?;
     let (state, _) = consume(state, ";")?;
-    if ret {
-        return Ok((
-            state,
-            Expr::Return {
-                expr: Box::new(expr),
-            },
-        ));
-    }
-    Ok((state, expr))
+    Ok((
+        state,
+        Expr::Return {
+            expr: Some(Box::new(expr)),
+            span: span_from(begin, state),
+        },
+    ))
+}
+
+fn parse_expression_statement(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, expr) = parse_expr(state)?;
+    let (state, _) = consume(state, ";")?;
+    Ok((
+        state,
+        Expr::ExprStmt {
+            expr: Box::new(expr),
+            span: span_from(begin, state),
+        },
+    ))
 }
 
 fn parse_while_statement(state: State) -> Answer<Expr> {
-    let (state, _) = consume(state, "while")?;
+    let (state, begin) = start(state)?;
+    let (state, _) = consume_keyword(state, "while")?;
     let (state, _) = consume(state, "(")?;
     let (state, expr) = parse_expr(state)?;
     let (state, _) = consume(state, ")")?;
@@ -750,80 +1866,288 @@ fn parse_while_statement(state: State) -> Answer<Expr> {
         Expr::While {
             cond: Box::new(expr),
             body,
+            span: span_from(begin, state),
         },
     ))
 }
 
-fn parse_statement(state: State) -> Answer<Expr> {
-    grammar(
+// the first clause of a `for`, including its `;`
+fn parse_for_init(state: State) -> Answer<Option<Box<Expr>>> {
+    let (state, empty) = text(state, ";")?;
+    if empty {
+        return Ok((state, None));
+    }
+    let (state, init) = grammar(
         "statement",
         &[
-            Box::new(|state| try_parser(parse_return_statement, state)),
             Box::new(|state| try_parser(parse_declaration_statement, state)),
-            Box::new(|state| try_parser(parse_selection_statement, state)),
-            Box::new(|state| try_parser(parse_while_statement, state)),
+            Box::new(|state| try_parser(parse_expression_statement, state)),
         ],
         state,
-    )
+    )?;
+    Ok((state, Some(Box::new(init))))
 }
 
-fn parse_paramlist<'a, 'b>(
-    args: &'b mut Vec<Arg>,
-    pat: &str,
-    state: State<'a>,
-) -> Answer<'a, &'b Vec<Arg>> {
-    let (state, matched) = text(state, pat)?;
-    if matched {
-        return Ok((state, args));
+// an optional expression followed by `end`
+fn parse_for_clause<'a>(state: State<'a>, end: &'a str) -> Answer<'a, Option<Box<Expr>>> {
+    let (new_state, empty) = text(state, end)?;
+    if empty {
+        return Ok((new_state, None));
     }
+    let (state, expr) = parse_expr(state)?;
+    let (state, _) = consume(state, end)?;
+    Ok((state, Some(Box::new(expr))))
+}
+
+fn parse_for_statement(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, _) = consume_keyword(state, "for")?;
+    let (state, _) = consume(state, "(")?;
+    let (state, init) = parse_for_init(state)?;
+    let (state, cond) = parse_for_clause(state, ";")?;
+    let (state, step) = parse_for_clause(state, ")")?;
+    let (state, body) = parse_body(state)?;
+    Ok((
+        state,
+        Expr::For {
+            init,
+            cond,
+            step,
+            body,
+            span: span_from(begin, state),
+        },
+    ))
+}
+
+fn parse_do_while_statement(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, _) = consume_keyword(state, "do")?;
+    let (state, body) = parse_body(state)?;
+    let (state, _) = consume_keyword(state, "while")?;
+    let (state, _) = consume(state, "(")?;
+    let (state, expr) = parse_expr(state)?;
+    let (state, _) = consume(state, ")")?;
+    let (state, _) = consume(state, ";")?;
+    Ok((
+        state,
+        Expr::DoWhile {
+            body,
+            cond: Box::new(expr),
+            span: span_from(begin, state),
+        },
+    ))
+}
+
+fn parse_jump_statement(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, is_break) = keyword(state, "break")?;
+    let state = if is_break {
+        state
+    } else {
+        consume_keyword(state, "continue")?.0
+    };
+    let (state, _) = consume(state, ";")?;
+    let span = span_from(begin, state);
+    let expr = if is_break {
+        Expr::Break { span }
+    } else {
+        Expr::Continue { span }
+    };
+    Ok((state, expr))
+}
+
+fn parse_switch_statement(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, _) = consume_keyword(state, "switch")?;
+    let (state, _) = consume(state, "(")?;
+    let (state, expr) = parse_expr(state)?;
+    let (state, _) = consume(state, ")")?;
+    let (state, body) = parse_compound_statement(state)?;
+    Ok((
+        state,
+        Expr::Switch {
+            cond: Box::new(expr),
+            body,
+            span: span_from(begin, state),
+        },
+    ))
+}
+
+fn parse_label(state: State) -> Answer<Expr> {
+    let (state, begin) = start(state)?;
+    let (state, is_default) = keyword(state, "default")?;
+    if is_default {
+        let (state, _) = consume(state, ":")?;
+        let span = span_from(begin, state);
+        return Ok((state, Expr::Default { span }));
+    }
+    let (state, _) = consume_keyword(state, "case")?;
+    let (state, expr) = parse_expr(state)?;
+    let (state, _) = consume(state, ":")?;
+    Ok((
+        state,
+        Expr::Case {
+            value: Box::new(expr),
+            span: span_from(begin, state),
+        },
+    ))
+}
+
+fn parse_statement(state: State) -> Answer<Expr> {
+    grammar(
+        "statement",
+        &[
+            Box::new(|state| try_parser(parse_return_statement, state)),
+            Box::new(|state| try_parser(parse_declaration_statement, state)),
+            Box::new(|state| try_parser(parse_selection_statement, state)),
+            Box::new(|state| try_parser(parse_while_statement, state)),
+            Box::new(|state| try_parser(parse_for_statement, state)),
+            Box::new(|state| try_parser(parse_do_while_statement, state)),
+            Box::new(|state| try_parser(parse_jump_statement, state)),
+            Box::new(|state| try_parser(parse_switch_statement, state)),
+            Box::new(|state| try_parser(parse_label, state)),
+            // last, so keywords are not taken for calls or variables
+            Box::new(|state| try_parser(parse_expression_statement, state)),
+        ],
+        state,
+    )
+}
+
+fn parse_paramlist<'a, 'b>(
+    args: &'b mut Vec<Arg>,
+    pat: &str,
+    state: State<'a>,
+) -> Answer<'a, &'b Vec<Arg>> {
+    let (state, matched) = text(state, pat)?;
+    if matched {
+        return Ok((state, args));
+    }
+    let state = if args.is_empty() {
+        state
+    } else {
+        separator(state, pat)?.0
+    };
+    let (state, begin) = start(state)?;
     let (state, ty) = parse_type(state)?;
     let (state, name) = name(state)?;
-    let (state, _) = text(state, ",")?;
-    args.push(Arg { name, ty });
+    // an array parameter is a pointer to its first element
+    let (state, sizes) = parse_array_sizes(state)?;
+    let (state, ty) = match sizes.split_first() {
+        Some((_, inner)) => {
+            let (state, elem) = array_type(state, ty, inner, None)?;
+            (state, Type::Pointer(Box::new(elem)))
+        }
+        None => (state, ty),
+    };
+    let span = span_from(begin, state);
+    args.push(Arg { name, ty, span });
     parse_paramlist(args, pat, state)
 }
 
 fn parse_function(state: State) -> Answer<Function> {
+    let (state, begin) = start(state)?;
+    let (state, is_static) = keyword(state, "static")?;
     let (state, ret_type) = parse_type(state)?;
     let (state, name) = name(state)?;
     let (state, _) = consume(state, "(")?;
     let mut args = Vec::new();
     let (state, _) = parse_paramlist(&mut args, ")", state)?;
-    let (state, exprs) = parse_compound_statement(state)?;
+    let span = span_from(begin, state);
+    // `f(void)` declares a function without parameters
+    if args.len() == 1 && args[0].ty == Type::Void && args[0].name.is_empty() {
+        args.clear();
+    }
+    let (state, prototype) = text(state, ";")?;
+    let (state, exprs) = if prototype {
+        (state, Vec::new())
+    } else {
+        parse_compound_statement(state)?
+    };
     let function = Function {
         ret_type,
         exprs,
         name,
         args,
+        prototype,
+        is_static,
+        span,
     };
     Ok((state, function))
 }
 
+fn parse_global(state: State) -> Answer<Global> {
+    let (state, begin) = start(state)?;
+    let (state, is_static) = keyword(state, "static")?;
+    let (state, (ty, name, init)) = parse_declaration(state)?;
+    let global = Global {
+        ty,
+        name,
+        init,
+        is_static,
+        span: span_from(begin, state),
+    };
+    Ok((state, global))
+}
+
+// a function is told from a variable by the `(` after its name
+fn is_function(state: State) -> bool {
+    let (state, _) = keyword(state, "static").unwrap_or((state, false));
+    match parse_type(state).and_then(|(state, _)| name(state)) {
+        Ok((state, _)) => text(state, "(").is_ok_and(|(_, matched)| matched),
+        // let the function parser report what is wrong
+        Err(_) => true,
+    }
+}
+
 fn parse_top_level(state: State) -> Answer<Program> {
     let mut state = state;
+    let mut globals: Vec<Global> = Vec::new();
     let mut functions: Vec<Function> = Vec::new();
     loop {
         let (new_state, _) = skip(state)?;
-        if new_state.rest().is_none() || new_state.rest().unwrap().len() == 0 {
+        if new_state.at_end() {
             break;
         }
-        let (new_state, function) = parse_function(new_state)?;
-        functions.push(function);
-        state = new_state;
+        let function = is_function(new_state);
+        // forget what the look ahead failed on
+        state.tracker.take_furthest();
+        if !function {
+            state = match parse_global(new_state) {
+                Ok((new_state, global)) => {
+                    globals.push(global);
+                    new_state
+                }
+                Err(err) => recover(new_state, err),
+            };
+            continue;
+        }
+        state = match parse_function(new_state) {
+            Ok((new_state, function)) => {
+                functions.push(function);
+                new_state
+            }
+            Err(err) => {
+                let state = recover(new_state, err);
+                // a stray `}` would stop recovery where it stands
+                match head(state) {
+                    Some('}') => tail(state),
+                    _ => state,
+                }
+            }
+        };
     }
-    Ok((state, Program { functions }))
+    Ok((state, Program { globals, functions }))
 }
 
-// parse a string of C code
-pub fn parse(code: &str) -> Result<Program, String> {
-    println!("\n\nparsing\n{}", code);
-    match parse_top_level(State { code, index: 0 }) {
-        Ok((_, value)) => Ok(value),
-        Err((msg, bt)) => {
-            println!("{:?}", bt);
-            Err(msg)
-        }
+// parse a string of C code, reporting every syntax error found
+pub fn parse(code: &str) -> Result<Program, Vec<ParseError>> {
+    let tracker = Tracker::default();
+    let (_, program) = parse_top_level(State::new(code, &tracker)).map_err(|err| vec![err])?;
+    let errors = tracker.errors.into_inner();
+    if !errors.is_empty() {
+        return Err(errors);
     }
+    Ok(program)
 }
 
 // tests
@@ -832,15 +2156,19 @@ mod tests {
     use std::hash::Hash;
 
     use crate::codegen::codegen;
+    use crate::codegen::tests::verified_ir;
+    use quickcheck::QuickCheck;
 
     use super::*;
 
     #[test]
     fn test_skip_whitespace() {
+        let tracker = Tracker::default();
         assert_eq!(
             skip(State {
                 code: "\n",
-                index: 0
+                index: 0,
+                tracker: &tracker,
             })
             .unwrap()
             .0
@@ -850,7 +2178,8 @@ mod tests {
         assert_eq!(
             skip(State {
                 code: "\n\n \t\n ",
-                index: 1
+                index: 1,
+                tracker: &tracker,
             })
             .unwrap()
             .0
@@ -859,6 +2188,110 @@ mod tests {
         );
     }
 
+    // the same program with every span cleared, to compare with one built
+    // by hand; spans get tests of their own
+    fn without_spans(mut program: Program) -> Program {
+        for global in &mut program.globals {
+            global.span = Span::default();
+            if let Some(init) = &mut global.init {
+                clear_spans(init);
+            }
+        }
+        for function in &mut program.functions {
+            function.span = Span::default();
+            for arg in &mut function.args {
+                arg.span = Span::default();
+            }
+            function.exprs.iter_mut().for_each(clear_spans);
+        }
+        program
+    }
+
+    fn clear_spans(expr: &mut Expr) {
+        match expr {
+            Expr::Int { span, .. }
+            | Expr::Var { span, .. }
+            | Expr::Break { span }
+            | Expr::Continue { span }
+            | Expr::Default { span } => *span = Span::default(),
+            Expr::ExprStmt { expr, span }
+            | Expr::Unary { expr, span, .. }
+            | Expr::IncDec { expr, span, .. }
+            | Expr::Deref { addr: expr, span }
+            | Expr::Case { value: expr, span } => {
+                *span = Span::default();
+                clear_spans(expr);
+            }
+            Expr::Return { expr, span }
+            | Expr::Decl {
+                init: expr, span, ..
+            } => {
+                *span = Span::default();
+                expr.iter_mut().for_each(|expr| clear_spans(expr));
+            }
+            Expr::BinOp { lhs, rhs, span, .. }
+            | Expr::Assign { lhs, rhs, span }
+            | Expr::CompoundAssign { lhs, rhs, span, .. }
+            | Expr::Index {
+                base: lhs,
+                index: rhs,
+                span,
+            } => {
+                *span = Span::default();
+                clear_spans(lhs);
+                clear_spans(rhs);
+            }
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                span,
+            } => {
+                *span = Span::default();
+                clear_spans(cond);
+                clear_spans(then);
+                clear_spans(otherwise);
+            }
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+                span,
+            } => {
+                *span = Span::default();
+                clear_spans(cond);
+                then.iter_mut().chain(otherwise).for_each(clear_spans);
+            }
+            Expr::While { cond, body, span }
+            | Expr::DoWhile { body, cond, span }
+            | Expr::Switch { cond, body, span } => {
+                *span = Span::default();
+                clear_spans(cond);
+                body.iter_mut().for_each(clear_spans);
+            }
+            Expr::InitList { values, span }
+            | Expr::Call {
+                args: values, span, ..
+            } => {
+                *span = Span::default();
+                values.iter_mut().for_each(clear_spans);
+            }
+            Expr::For {
+                init,
+                cond,
+                step,
+                body,
+                span,
+            } => {
+                *span = Span::default();
+                for expr in [init, cond, step].into_iter().flatten() {
+                    clear_spans(expr);
+                }
+                body.iter_mut().for_each(clear_spans);
+            }
+        }
+    }
+
     fn codegen_code(code: &str, program: &Program) {
         let hash = {
             use std::collections::hash_map::DefaultHasher;
@@ -867,9 +2300,12 @@ mod tests {
             code.hash(&mut hasher);
             hasher.finish()
         };
-        let file_path = format!("out/{}.out", hash.to_string());
+        // the output directory is ignored by git, so a fresh checkout lacks it
+        std::fs::create_dir_all("out").unwrap();
+        let file_path = format!("out/{}.out", hash);
         println!("writing to {}", file_path);
-        codegen(program, &file_path);
+        verified_ir(program).unwrap();
+        codegen(program, &file_path).unwrap();
         // print the file to stdout
         let output = std::process::Command::new("cat")
             .arg(&file_path)
@@ -879,7 +2315,7 @@ mod tests {
     }
 
     fn test_main1(code: &str, ret_type: Type, exprs: Vec<Expr>) {
-        let program = parse(code).unwrap();
+        let program = without_spans(parse(code).unwrap());
         let function = &program.functions[0];
         assert_eq!(program.functions.len(), 1);
         assert_eq!(function.name, "main");
@@ -893,7 +2329,11 @@ mod tests {
         let code = "int main() { return 0; }";
         let ret_type = Type::Int;
         let ret_expr = Expr::Return {
-            expr: Box::new(Expr::Int { value: 0 }),
+            expr: Some(Box::new(Expr::Int {
+                value: 0,
+                span: Span::default(),
+            })),
+            span: Span::default(),
         };
         let exprs = vec![ret_expr];
         test_main1(code, ret_type, exprs);
@@ -903,10 +2343,20 @@ mod tests {
     fn test_void1() {
         let code = "void main() { 42 + 24; }";
         let ret_type = Type::Void;
-        let exprs = vec![Expr::BinOp {
-            op: Op::Add,
-            lhs: Box::new(Expr::Int { value: 42 }),
-            rhs: Box::new(Expr::Int { value: 24 }),
+        let exprs = vec![Expr::ExprStmt {
+            expr: Box::new(Expr::BinOp {
+                op: Op::Add,
+                lhs: Box::new(Expr::Int {
+                    value: 42,
+                    span: Span::default(),
+                }),
+                rhs: Box::new(Expr::Int {
+                    value: 24,
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }),
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -916,13 +2366,27 @@ mod tests {
         let code = "int main() { 2 + 3; return 0; }";
         let ret_type = Type::Int;
         let exprs = vec![
-            Expr::BinOp {
-                op: Op::Add,
-                lhs: Box::new(Expr::Int { value: 2 }),
-                rhs: Box::new(Expr::Int { value: 3 }),
+            Expr::ExprStmt {
+                expr: Box::new(Expr::BinOp {
+                    op: Op::Add,
+                    lhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 3,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                }),
+                span: Span::default(),
             },
             Expr::Return {
-                expr: Box::new(Expr::Int { value: 0 }),
+                expr: Some(Box::new(Expr::Int {
+                    value: 0,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             },
         ];
         test_main1(code, ret_type, exprs);
@@ -933,13 +2397,25 @@ mod tests {
         let code = "int main() { if (1) { return 0; } else { return 1; } }";
         let ret_type = Type::Int;
         let exprs = vec![Expr::If {
-            cond: Box::new(Expr::Int { value: 1 }),
+            cond: Box::new(Expr::Int {
+                value: 1,
+                span: Span::default(),
+            }),
             then: vec![Expr::Return {
-                expr: Box::new(Expr::Int { value: 0 }),
+                expr: Some(Box::new(Expr::Int {
+                    value: 0,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             }],
             otherwise: vec![Expr::Return {
-                expr: Box::new(Expr::Int { value: 1 }),
+                expr: Some(Box::new(Expr::Int {
+                    value: 1,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             }],
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -951,15 +2427,31 @@ mod tests {
         let exprs = vec![Expr::If {
             cond: Box::new(Expr::BinOp {
                 op: Op::Gt,
-                lhs: Box::new(Expr::Int { value: 2 }),
-                rhs: Box::new(Expr::Int { value: 1 }),
+                lhs: Box::new(Expr::Int {
+                    value: 2,
+                    span: Span::default(),
+                }),
+                rhs: Box::new(Expr::Int {
+                    value: 1,
+                    span: Span::default(),
+                }),
+                span: Span::default(),
             }),
             then: vec![Expr::Return {
-                expr: Box::new(Expr::Int { value: 0 }),
+                expr: Some(Box::new(Expr::Int {
+                    value: 0,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             }],
             otherwise: vec![Expr::Return {
-                expr: Box::new(Expr::Int { value: 1 }),
+                expr: Some(Box::new(Expr::Int {
+                    value: 1,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             }],
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -973,23 +2465,42 @@ mod tests {
                 ty: Type::Int,
                 name: "a".to_string(),
                 init: Some(Box::new(Expr::Deref {
-                    addr: Box::new(Expr::Int { value: 0x400 }),
+                    addr: Box::new(Expr::Int {
+                        value: 0x400,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 })),
+                span: Span::default(),
             },
             Expr::If {
                 cond: Box::new(Expr::BinOp {
                     op: Op::Gt,
                     lhs: Box::new(Expr::Var {
                         name: "a".to_string(),
+                        span: Span::default(),
                     }),
-                    rhs: Box::new(Expr::Int { value: 123 }),
+                    rhs: Box::new(Expr::Int {
+                        value: 123,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
                 then: vec![Expr::Return {
-                    expr: Box::new(Expr::Int { value: 42 }),
+                    expr: Some(Box::new(Expr::Int {
+                        value: 42,
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
                 }],
                 otherwise: vec![Expr::Return {
-                    expr: Box::new(Expr::Int { value: 24 }),
+                    expr: Some(Box::new(Expr::Int {
+                        value: 24,
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
                 }],
+                span: Span::default(),
             },
         ];
         test_main1(code, ret_type, exprs);
@@ -1004,29 +2515,50 @@ mod tests {
                 ty: Type::Int,
                 name: "a".to_string(),
                 init: None,
+                span: Span::default(),
             },
-            Expr::Assign {
-                lhs: Box::new(Expr::Var {
-                    name: "a".to_string(),
-                }),
-                rhs: Box::new(Expr::Var {
-                    name: "x".to_string(),
+            Expr::ExprStmt {
+                expr: Box::new(Expr::Assign {
+                    lhs: Box::new(Expr::Var {
+                        name: "a".to_string(),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Var {
+                        name: "x".to_string(),
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             },
             Expr::If {
                 cond: Box::new(Expr::BinOp {
                     op: Op::Gt,
                     lhs: Box::new(Expr::Var {
                         name: "a".to_string(),
+                        span: Span::default(),
                     }),
-                    rhs: Box::new(Expr::Int { value: 0 }),
+                    rhs: Box::new(Expr::Int {
+                        value: 0,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
                 then: vec![Expr::Return {
-                    expr: Box::new(Expr::Int { value: 0 }),
+                    expr: Some(Box::new(Expr::Int {
+                        value: 0,
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
                 }],
                 otherwise: vec![Expr::Return {
-                    expr: Box::new(Expr::Int { value: 1 }),
+                    expr: Some(Box::new(Expr::Int {
+                        value: 1,
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
                 }],
+                span: Span::default(),
             },
         ];
         test_main1(code, ret_type, exprs);
@@ -1041,41 +2573,78 @@ mod tests {
                 ty: Type::Int,
                 name: "a".to_string(),
                 init: Some(Box::new(Expr::Deref {
-                    addr: Box::new(Expr::Int { value: 0x400 }),
+                    addr: Box::new(Expr::Int {
+                        value: 0x400,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 })),
+                span: Span::default(),
             },
             Expr::If {
                 cond: Box::new(Expr::BinOp {
                     op: Op::Gt,
                     lhs: Box::new(Expr::Var {
                         name: "a".to_string(),
+                        span: Span::default(),
                     }),
-                    rhs: Box::new(Expr::Int { value: 123 }),
+                    rhs: Box::new(Expr::Int {
+                        value: 123,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
                 then: vec![Expr::Return {
-                    expr: Box::new(Expr::Int { value: 42 }),
+                    expr: Some(Box::new(Expr::Int {
+                        value: 42,
+                        span: Span::default(),
+                    })),
+                    span: Span::default(),
                 }],
                 otherwise: vec![Expr::If {
                     cond: Box::new(Expr::BinOp {
                         op: Op::Lt,
                         lhs: Box::new(Expr::Var {
                             name: "a".to_string(),
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 100,
+                            span: Span::default(),
                         }),
-                        rhs: Box::new(Expr::Int { value: 100 }),
+                        span: Span::default(),
                     }),
-                    then: vec![Expr::Assign {
-                        lhs: Box::new(Expr::Var {
-                            name: "a".to_string(),
+                    then: vec![Expr::ExprStmt {
+                        expr: Box::new(Expr::Assign {
+                            lhs: Box::new(Expr::Var {
+                                name: "a".to_string(),
+                                span: Span::default(),
+                            }),
+                            rhs: Box::new(Expr::Int {
+                                value: 10,
+                                span: Span::default(),
+                            }),
+                            span: Span::default(),
                         }),
-                        rhs: Box::new(Expr::Int { value: 10 }),
+                        span: Span::default(),
                     }],
-                    otherwise: vec![Expr::Assign {
-                        lhs: Box::new(Expr::Var {
-                            name: "a".to_string(),
+                    otherwise: vec![Expr::ExprStmt {
+                        expr: Box::new(Expr::Assign {
+                            lhs: Box::new(Expr::Var {
+                                name: "a".to_string(),
+                                span: Span::default(),
+                            }),
+                            rhs: Box::new(Expr::Int {
+                                value: 20,
+                                span: Span::default(),
+                            }),
+                            span: Span::default(),
                         }),
-                        rhs: Box::new(Expr::Int { value: 20 }),
+                        span: Span::default(),
                     }],
+                    span: Span::default(),
                 }],
+                span: Span::default(),
             },
         ];
         test_main1(code, ret_type, exprs);
@@ -1087,11 +2656,19 @@ mod tests {
         let ret_type = Type::Int;
         let expr = Expr::BinOp {
             op: Op::Add,
-            lhs: Box::new(Expr::Int { value: 3 }),
-            rhs: Box::new(Expr::Int { value: 2 }),
+            lhs: Box::new(Expr::Int {
+                value: 3,
+                span: Span::default(),
+            }),
+            rhs: Box::new(Expr::Int {
+                value: 2,
+                span: Span::default(),
+            }),
+            span: Span::default(),
         };
         let exprs = vec![Expr::Return {
-            expr: Box::new(expr),
+            expr: Some(Box::new(expr)),
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -1101,12 +2678,28 @@ mod tests {
         let code = "void main(int ls, int js) { (0 + 3738978009); 2714820978 ; }";
         let ret_type = Type::Void;
         let exprs = vec![
-            Expr::BinOp {
-                op: Op::Add,
-                lhs: Box::new(Expr::Int { value: 0 }),
-                rhs: Box::new(Expr::Int { value: 3738978009 }),
+            Expr::ExprStmt {
+                expr: Box::new(Expr::BinOp {
+                    op: Op::Add,
+                    lhs: Box::new(Expr::Int {
+                        value: 0,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 3738978009,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            },
+            Expr::ExprStmt {
+                expr: Box::new(Expr::Int {
+                    value: 2714820978,
+                    span: Span::default(),
+                }),
+                span: Span::default(),
             },
-            Expr::Int { value: 2714820978 },
         ];
         test_main1(code, ret_type, exprs);
     }
@@ -1120,34 +2713,64 @@ mod tests {
                 ty: Type::Int,
                 name: "a".to_string(),
                 init: None,
+                span: Span::default(),
             },
             Expr::Decl {
                 ty: Type::Int,
                 name: "b".to_string(),
                 init: None,
+                span: Span::default(),
             },
-            Expr::Assign {
-                lhs: Box::new(Expr::Var {
-                    name: "a".to_string(),
+            Expr::ExprStmt {
+                expr: Box::new(Expr::Assign {
+                    lhs: Box::new(Expr::Var {
+                        name: "a".to_string(),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
-                rhs: Box::new(Expr::Int { value: 2 }),
+                span: Span::default(),
             },
-            Expr::Assign {
-                lhs: Box::new(Expr::Var {
-                    name: "b".to_string(),
-                }),
-                rhs: Box::new(Expr::BinOp {
-                    op: Op::Add,
-                    lhs: Box::new(Expr::Int { value: 3 }),
-                    rhs: Box::new(Expr::Int { value: 2 }),
+            Expr::ExprStmt {
+                expr: Box::new(Expr::Assign {
+                    lhs: Box::new(Expr::Var {
+                        name: "b".to_string(),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::BinOp {
+                        op: Op::Add,
+                        lhs: Box::new(Expr::Int {
+                            value: 3,
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 2,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             },
             Expr::Return {
-                expr: Box::new(Expr::BinOp {
+                expr: Some(Box::new(Expr::BinOp {
                     op: Op::Add,
-                    lhs: Box::new(Expr::Int { value: 1 }),
-                    rhs: Box::new(Expr::Int { value: 2 }),
-                }),
+                    lhs: Box::new(Expr::Int {
+                        value: 1,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             },
         ];
         test_main1(code, ret_type, exprs);
@@ -1161,6 +2784,7 @@ mod tests {
             ty: Type::Int,
             name: "a".to_string(),
             init: None,
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -1173,23 +2797,43 @@ mod tests {
             Expr::Decl {
                 ty: Type::Int,
                 name: "a".to_string(),
-                init: Some(Box::new(Expr::Int { value: 2 })),
+                init: Some(Box::new(Expr::Int {
+                    value: 2,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             },
             Expr::Decl {
                 ty: Type::Int,
                 name: "b".to_string(),
                 init: Some(Box::new(Expr::BinOp {
                     op: Op::Add,
-                    lhs: Box::new(Expr::Int { value: 3 }),
-                    rhs: Box::new(Expr::Int { value: 2 }),
+                    lhs: Box::new(Expr::Int {
+                        value: 3,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 })),
+                span: Span::default(),
             },
             Expr::Return {
-                expr: Box::new(Expr::BinOp {
+                expr: Some(Box::new(Expr::BinOp {
                     op: Op::Add,
-                    lhs: Box::new(Expr::Int { value: 1 }),
-                    rhs: Box::new(Expr::Int { value: 2 }),
-                }),
+                    lhs: Box::new(Expr::Int {
+                        value: 1,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                })),
+                span: Span::default(),
             },
         ];
         test_main1(code, ret_type, exprs);
@@ -1201,23 +2845,43 @@ mod tests {
         let ret_type = Type::Int;
         let expr = Expr::BinOp {
             op: Op::Add,
-            lhs: Box::new(Expr::Int { value: 3 }),
-            rhs: Box::new(Expr::BinOp {
+            lhs: Box::new(Expr::BinOp {
                 op: Op::Add,
-                lhs: Box::new(Expr::Int { value: 2 }),
+                lhs: Box::new(Expr::Int {
+                    value: 3,
+                    span: Span::default(),
+                }),
+                rhs: Box::new(Expr::Int {
+                    value: 2,
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }),
+            rhs: Box::new(Expr::BinOp {
+                op: Op::Sub,
+                lhs: Box::new(Expr::Int {
+                    value: 5,
+                    span: Span::default(),
+                }),
                 rhs: Box::new(Expr::BinOp {
-                    op: Op::Sub,
-                    lhs: Box::new(Expr::Int { value: 5 }),
-                    rhs: Box::new(Expr::BinOp {
-                        op: Op::Mul,
-                        lhs: Box::new(Expr::Int { value: 7 }),
-                        rhs: Box::new(Expr::Int { value: 10 }),
+                    op: Op::Mul,
+                    lhs: Box::new(Expr::Int {
+                        value: 7,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 10,
+                        span: Span::default(),
                     }),
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             }),
+            span: Span::default(),
         };
         let exprs = vec![Expr::Return {
-            expr: Box::new(expr),
+            expr: Some(Box::new(expr)),
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -1227,21 +2891,39 @@ mod tests {
         let code = "int main() { while (1) { *0x08000000 = *0x08010000 + 1; } }";
         let ret_type = Type::Int;
         let exprs = vec![Expr::While {
-            cond: Box::new(Expr::Int { value: 1 }),
-            body: vec![
-                Expr::Assign {
+            cond: Box::new(Expr::Int {
+                value: 1,
+                span: Span::default(),
+            }),
+            body: vec![Expr::ExprStmt {
+                expr: Box::new(Expr::Assign {
                     lhs: Box::new(Expr::Deref {
-                        addr: Box::new(Expr::Int { value: 0x08000000 }),
+                        addr: Box::new(Expr::Int {
+                            value: 0x08000000,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
                     }),
                     rhs: Box::new(Expr::BinOp {
                         op: Op::Add,
                         lhs: Box::new(Expr::Deref {
-                            addr: Box::new(Expr::Int { value: 0x08010000 }),
+                            addr: Box::new(Expr::Int {
+                                value: 0x08010000,
+                                span: Span::default(),
+                            }),
+                            span: Span::default(),
                         }),
-                        rhs: Box::new(Expr::Int { value: 1 }),
+                        rhs: Box::new(Expr::Int {
+                            value: 1,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
                     }),
-                },
-            ],
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }],
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -1251,19 +2933,35 @@ mod tests {
         let code = "int main() { return 3 + 2 && (9 || 2); }";
         let ret_type = Type::Int;
         let exprs = vec![Expr::Return {
-            expr: Box::new(Expr::BinOp {
+            expr: Some(Box::new(Expr::BinOp {
                 op: Op::And,
                 lhs: Box::new(Expr::BinOp {
                     op: Op::Add,
-                    lhs: Box::new(Expr::Int { value: 3 }),
-                    rhs: Box::new(Expr::Int { value: 2 }),
+                    lhs: Box::new(Expr::Int {
+                        value: 3,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
                 rhs: Box::new(Expr::BinOp {
                     op: Op::Or,
-                    lhs: Box::new(Expr::Int { value: 9 }),
-                    rhs: Box::new(Expr::Int { value: 2 }),
+                    lhs: Box::new(Expr::Int {
+                        value: 9,
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 2,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
-            }),
+                span: Span::default(),
+            })),
+            span: Span::default(),
         }];
         test_main1(code, ret_type, exprs);
     }
@@ -1277,61 +2975,725 @@ mod tests {
                 ty: Type::Int,
                 name: "a".to_string(),
                 init: None,
+                span: Span::default(),
             },
             Expr::Return {
-            expr: Box::new(Expr::BinOp {
-                op: Op::Or,
-                lhs: Box::new(Expr::BinOp {
-                    op: Op::And,
+                expr: Some(Box::new(Expr::BinOp {
+                    op: Op::Or,
                     lhs: Box::new(Expr::BinOp {
-                        op: Op::Add,
-                        lhs: Box::new(Expr::Int { value: 3 }),
-                        rhs: Box::new(Expr::Int { value: 2 }),
-                    }),
-                    rhs: Box::new(Expr::BinOp {
                         op: Op::And,
                         lhs: Box::new(Expr::BinOp {
-                            op: Op::Or,
-                            lhs: Box::new(Expr::Int { value: 9 }),
+                            op: Op::And,
+                            lhs: Box::new(Expr::BinOp {
+                                op: Op::Add,
+                                lhs: Box::new(Expr::Int {
+                                    value: 3,
+                                    span: Span::default(),
+                                }),
+                                rhs: Box::new(Expr::Int {
+                                    value: 2,
+                                    span: Span::default(),
+                                }),
+                                span: Span::default(),
+                            }),
                             rhs: Box::new(Expr::BinOp {
-                                op: Op::Lt,
-                                lhs: Box::new(Expr::Int { value: 2 }),
+                                op: Op::Or,
+                                lhs: Box::new(Expr::Int {
+                                    value: 9,
+                                    span: Span::default(),
+                                }),
                                 rhs: Box::new(Expr::BinOp {
                                     op: Op::Ge,
                                     lhs: Box::new(Expr::BinOp {
-                                        op: Op::Mul,
-                                        lhs: Box::new(Expr::Var {
-                                            name: "a".to_string(),
+                                        op: Op::Lt,
+                                        lhs: Box::new(Expr::Int {
+                                            value: 2,
+                                            span: Span::default(),
                                         }),
-                                        rhs: Box::new(Expr::Int { value: 5 }),
+                                        rhs: Box::new(Expr::BinOp {
+                                            op: Op::Mul,
+                                            lhs: Box::new(Expr::Var {
+                                                name: "a".to_string(),
+                                                span: Span::default(),
+                                            }),
+                                            rhs: Box::new(Expr::Int {
+                                                value: 5,
+                                                span: Span::default(),
+                                            }),
+                                            span: Span::default(),
+                                        }),
+                                        span: Span::default(),
+                                    }),
+                                    rhs: Box::new(Expr::Int {
+                                        value: 8,
+                                        span: Span::default(),
                                     }),
-                                    rhs: Box::new(Expr::Int { value: 8 }),
+                                    span: Span::default(),
                                 }),
+                                span: Span::default(),
+                            }),
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 7,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::BinOp {
+                        op: Op::Gt,
+                        lhs: Box::new(Expr::Int {
+                            value: 5,
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 23,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                })),
+                span: Span::default(),
+            },
+        ];
+        test_main1(code, ret_type, exprs);
+    }
+
+    #[test]
+    fn test_call1() {
+        let code = "int f(int x, int y) { return x; } int main() { int x = 6; return f(x, x); }";
+        let program = without_spans(parse(code).unwrap());
+        assert_eq!(program.functions.len(), 2);
+        assert_eq!(
+            program.functions[1].exprs[1],
+            Expr::Return {
+                expr: Some(Box::new(Expr::Call {
+                    name: "f".to_string(),
+                    args: vec![
+                        Expr::Var {
+                            name: "x".to_string(),
+                            span: Span::default(),
+                        },
+                        Expr::Var {
+                            name: "x".to_string(),
+                            span: Span::default(),
+                        },
+                    ],
+                    span: Span::default(),
+                })),
+                span: Span::default(),
+            }
+        );
+        codegen_code(code, &program);
+    }
+
+    #[test]
+    fn test_call2() {
+        let code = "int g() { return 2; } int main() { while (g() > 1) { *0x400 = g() + 1; } }";
+        let program = without_spans(parse(code).unwrap());
+        assert_eq!(
+            program.functions[1].exprs,
+            vec![Expr::While {
+                cond: Box::new(Expr::BinOp {
+                    op: Op::Gt,
+                    lhs: Box::new(Expr::Call {
+                        name: "g".to_string(),
+                        args: vec![],
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 1,
+                        span: Span::default()
+                    }),
+                    span: Span::default(),
+                }),
+                body: vec![Expr::ExprStmt {
+                    expr: Box::new(Expr::Assign {
+                        lhs: Box::new(Expr::Deref {
+                            addr: Box::new(Expr::Int {
+                                value: 0x400,
+                                span: Span::default()
+                            }),
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::BinOp {
+                            op: Op::Add,
+                            lhs: Box::new(Expr::Call {
+                                name: "g".to_string(),
+                                args: vec![],
+                                span: Span::default(),
+                            }),
+                            rhs: Box::new(Expr::Int {
+                                value: 1,
+                                span: Span::default()
                             }),
+                            span: Span::default(),
                         }),
-                        rhs: Box::new(Expr::Int { value: 7 }),
+                        span: Span::default(),
                     }),
+                    span: Span::default(),
+                }],
+                span: Span::default(),
+            }]
+        );
+        codegen_code(code, &program);
+    }
+
+    #[test]
+    fn test_commas1() {
+        let call = |args: &str| {
+            parse(&format!(
+                "int f(int a, int b) {{ return a; }} int main() {{ return f({}); }}",
+                args
+            ))
+        };
+        assert!(call("1, 2").is_ok());
+        let errors = call("1 2").unwrap_err();
+        assert_eq!(errors[0].to_string(), "error: expected `)` or `,`");
+        let errors = call("1, 2,").unwrap_err();
+        assert_eq!(errors[0].to_string(), "error: expected `expression`");
+        let errors = parse("int f(int a int b) { return a; }").unwrap_err();
+        assert_eq!(errors[0].to_string(), "error: expected `)` or `,`");
+        let errors = parse("int f(int a,) { return a; }").unwrap_err();
+        assert_eq!(errors[0].to_string(), "error: expected `type`");
+    }
+
+    #[test]
+    fn test_prototype1() {
+        let code = "int f(int x); int main() { return f(2); } int f(int x) { return x + 1; }";
+        let program = without_spans(parse(code).unwrap());
+        assert_eq!(program.functions.len(), 3);
+        assert_eq!(
+            program.functions[0],
+            Function {
+                ret_type: Type::Int,
+                exprs: vec![],
+                name: "f".to_string(),
+                args: vec![Arg {
+                    ty: Type::Int,
+                    name: "x".to_string(),
+                    span: Span::default(),
+                }],
+                prototype: true,
+                is_static: false,
+                span: Span::default(),
+            }
+        );
+        assert!(!program.functions[2].prototype);
+        codegen_code(code, &program);
+    }
+
+    #[test]
+    fn test_mutual_recursion1() {
+        let code = "int even(int n) { if (n == 0) { return 1; } else { return odd(n - 1); } } int odd(int n) { if (n == 0) { return 0; } else { return even(n - 1); } }";
+        let program = parse(code).unwrap();
+        assert_eq!(program.functions.len(), 2);
+        codegen_code(code, &program);
+    }
+
+    #[test]
+    fn test_if_without_else1() {
+        let code = "void main(int x) { if (x) return; if (x > 1) { x = 2; } else x = 3; }";
+        let ret_type = Type::Void;
+        let exprs = vec![
+            Expr::If {
+                cond: Box::new(Expr::Var {
+                    name: "x".to_string(),
+                    span: Span::default(),
                 }),
-                rhs: Box::new(Expr::BinOp {
+                then: vec![Expr::Return {
+                    expr: None,
+                    span: Span::default(),
+                }],
+                otherwise: vec![],
+                span: Span::default(),
+            },
+            Expr::If {
+                cond: Box::new(Expr::BinOp {
                     op: Op::Gt,
-                    lhs: Box::new(Expr::Int { value: 5 }),
-                    rhs: Box::new(Expr::Int { value: 23 }),
+                    lhs: Box::new(Expr::Var {
+                        name: "x".to_string(),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 1,
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
                 }),
-            }),
-        }];
+                then: vec![Expr::ExprStmt {
+                    expr: Box::new(Expr::Assign {
+                        lhs: Box::new(Expr::Var {
+                            name: "x".to_string(),
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 2,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                }],
+                otherwise: vec![Expr::ExprStmt {
+                    expr: Box::new(Expr::Assign {
+                        lhs: Box::new(Expr::Var {
+                            name: "x".to_string(),
+                            span: Span::default(),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 3,
+                            span: Span::default(),
+                        }),
+                        span: Span::default(),
+                    }),
+                    span: Span::default(),
+                }],
+                span: Span::default(),
+            },
+        ];
+        test_main1(code, ret_type, exprs);
+    }
+
+    #[test]
+    fn test_expression_statement1() {
+        let code = "void f() { return; } int main() { int x; x = 1; f(); return x; }";
+        let program = without_spans(parse(code).unwrap());
+        let exprs = &program.functions[1].exprs;
+        assert_eq!(
+            exprs[1],
+            Expr::ExprStmt {
+                expr: Box::new(Expr::Assign {
+                    lhs: Box::new(Expr::Var {
+                        name: "x".to_string(),
+                        span: Span::default(),
+                    }),
+                    rhs: Box::new(Expr::Int {
+                        value: 1,
+                        span: Span::default()
+                    }),
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }
+        );
+        assert_eq!(
+            exprs[2],
+            Expr::ExprStmt {
+                expr: Box::new(Expr::Call {
+                    name: "f".to_string(),
+                    args: vec![],
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            }
+        );
+        assert!(matches!(exprs[3], Expr::Return { expr: Some(_), .. }));
+        codegen_code(code, &program);
+    }
+
+    #[test]
+    fn test_unary1() {
+        let code = "int main() { int x = 1; return -x * !~&x; }";
+        let ret_type = Type::Int;
+        let var = || {
+            Box::new(Expr::Var {
+                name: "x".to_string(),
+                span: Span::default(),
+            })
+        };
+        let unary = |op, expr| {
+            Box::new(Expr::Unary {
+                op,
+                expr,
+                span: Span::default(),
+            })
+        };
+        let exprs = vec![
+            Expr::Decl {
+                ty: Type::Int,
+                name: "x".to_string(),
+                init: Some(Box::new(Expr::Int {
+                    value: 1,
+                    span: Span::default(),
+                })),
+                span: Span::default(),
+            },
+            Expr::Return {
+                expr: Some(Box::new(Expr::BinOp {
+                    op: Op::Mul,
+                    lhs: unary(UnaryOp::Neg, var()),
+                    rhs: unary(
+                        UnaryOp::Not,
+                        unary(UnaryOp::BitNot, unary(UnaryOp::AddrOf, var())),
+                    ),
+                    span: Span::default(),
+                })),
+                span: Span::default(),
+            },
+        ];
         test_main1(code, ret_type, exprs);
     }
 
+    #[test]
+    fn test_precedence1() {
+        let code = "int main() { return a - b - c % d << 1 & e ^ f | g == h && i | j >> k; }";
+        let program = parse(code).unwrap();
+        assert_eq!(
+            program.functions[0].exprs[0].deparse(),
+            "return (((((((a - b) - (c % d)) << 1) & e) ^ f) | (g == h)) && (i | (j >> k)))"
+        );
+    }
+
+    #[test]
+    fn test_compound_assign1() {
+        let code = "int main() { int x; x <<= 2; *x -= x == 1; return x++ + --x - -x; }";
+        let program = parse(code).unwrap();
+        let stmts = program.functions[0]
+            .exprs
+            .iter()
+            .map(|e| e.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            vec![
+                "int x",
+                "x <<= 2",
+                "*x -= (x == 1)",
+                "return (((x++) + (--x)) - (-x))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loops1() {
+        let code = "int main() {
+            for (int i = 0; i < 3; i++) { if (i) { continue; } }
+            for (;;) break;
+            do x += 1; while (x < 5);
+        }";
+        let program = parse(code).unwrap();
+        let stmts = program.functions[0]
+            .exprs
+            .iter()
+            .map(|e| e.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            vec![
+                "for (int i = 0; (i < 3); (i++)) {\nif (i) {\ncontinue;\n} else {\n;\n};\n}",
+                "for (; ; ) {\nbreak;\n}",
+                "do {\nx += 1;\n} while ((x < 5))",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_switch1() {
+        let code = "int main(int x) { switch (x) { case 1: case 2 * 2: x = 0; break; default: return x; } }";
+        let program = parse(code).unwrap();
+        assert_eq!(
+            program.functions[0].exprs[0].deparse(),
+            "switch (x) {\ncase 1:;\ncase (2 * 2):;\nx = 0;\nbreak;\ndefault:;\nreturn x;\n}"
+        );
+    }
+
+    #[test]
+    fn test_const_value1() {
+        let value = |code: &str| {
+            let code = format!("int main() {{ return {}; }}", code);
+            match &parse(&code).unwrap().functions[0].exprs[0] {
+                Expr::Return {
+                    expr: Some(expr), ..
+                } => expr.const_value(),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(value("-(1 << 4) % 5 + !0"), Some(0));
+        assert_eq!(value("-1 >> 31 == ~0 && 3"), Some(1));
+        assert_eq!(value("1 / 0"), None);
+        assert_eq!(value("1 << 32"), None);
+        assert_eq!(value("0 ? x : 2 ? 3 : 4"), Some(3));
+        assert_eq!(value("x + 1"), None);
+        // a literal that does not fit an int is unsigned, and so is what it
+        // takes part in
+        assert_eq!(value("0xFFFFFFFF / 2"), Some(0x7FFFFFFF));
+        assert_eq!(value("0xFFFFFFFF % 10"), Some(5));
+        assert_eq!(value("0x80000000 >> 31"), Some(1));
+        assert_eq!(value("-1 < 0"), Some(1));
+        assert_eq!(value("-1 < 0x80000000"), Some(0));
+        assert_eq!(value("1 ? 0xFFFFFFFF : 0 > 0"), Some(-1));
+        assert_eq!(value("(1 ? 0xFFFFFFFF : 0) > 0"), Some(1));
+    }
+
+    #[test]
+    fn test_ternary1() {
+        let code = "int main() { x = a ? b = 1 : c ? d : e || f; return a && b ? 1 : 2; }";
+        let program = parse(code).unwrap();
+        let stmts = program.functions[0]
+            .exprs
+            .iter()
+            .map(|e| e.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            vec![
+                "x = (a ? b = 1 : (c ? d : (e || f)))",
+                "return ((a && b) ? 1 : 2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keyword_prefix1() {
+        let code = "int main() { int returned = 1; int breakfast = 2; int dough = 3; int format = 4; int iffy = 5; int whilst = 6; int cases = 7; int defaults = 8; int switched = 9; int continued = 10; returned = breakfast + dough; format = iffy; whilst = cases; defaults = switched + continued; return returned; }";
+        let program = parse(code).unwrap();
+        let stmts = program.functions[0]
+            .exprs
+            .iter()
+            .map(|e| e.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            &stmts[10..],
+            [
+                "returned = (breakfast + dough)",
+                "format = iffy",
+                "whilst = cases",
+                "defaults = (switched + continued)",
+                "return returned",
+            ]
+        );
+        assert!(parse("int main() { do2 = 1; return do2; }").is_ok());
+    }
+
+    #[test]
+    fn test_pointer1() {
+        let code = "char *f(char *s, volatile int *reg) { int*p; int volatile * volatile *q; integer = 1; return s; }";
+        let program = parse(code).unwrap();
+        let function = &program.functions[0];
+        let volatile_int = Type::Volatile(Box::new(Type::Int));
+        assert_eq!(function.ret_type, Type::Pointer(Box::new(Type::Char)));
+        assert_eq!(
+            function.args[1].ty,
+            Type::Pointer(Box::new(volatile_int.clone()))
+        );
+        let stmts = function
+            .exprs
+            .iter()
+            .map(|e| e.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            vec![
+                "int* p",
+                "volatile int* volatile* q",
+                "integer = 1",
+                "return s",
+            ]
+        );
+        match &function.exprs[1] {
+            Expr::Decl { ty, .. } => assert_eq!(
+                *ty,
+                Type::Pointer(Box::new(Type::Volatile(Box::new(Type::Pointer(Box::new(
+                    volatile_int
+                ))))))
+            ),
+            expr => panic!("unexpected {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn test_array1() {
+        let code = "int f(int v[], char s[4]) { int buf[64]; int a[] = {1, 2, 3,}; int m[2][3] = {{1}, {4, 5}}; m[1][a[0]] = v[2]; return buf[0]; }";
+        let program = parse(code).unwrap();
+        let function = &program.functions[0];
+        assert_eq!(function.args[0].ty, Type::Pointer(Box::new(Type::Int)));
+        assert_eq!(function.args[1].ty, Type::Pointer(Box::new(Type::Char)));
+        let stmts = function
+            .exprs
+            .iter()
+            .map(|e| e.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stmts,
+            vec![
+                "int buf[64]",
+                "int a[3] = {1, 2, 3}",
+                "int m[2][3] = {{1}, {4, 5}}",
+                "m[1][a[0]] = v[2]",
+                "return buf[0]",
+            ]
+        );
+        match &function.exprs[2] {
+            Expr::Decl { ty, .. } => assert_eq!(
+                *ty,
+                Type::Array(Box::new(Type::Array(Box::new(Type::Int), 3)), 2)
+            ),
+            expr => panic!("unexpected {:?}", expr),
+        }
+        assert!(parse("int main() { int m[][] = {{1}}; return 0; }").is_err());
+        assert!(parse("int main() { int a[]; return 0; }").is_err());
+    }
+
+    #[test]
+    fn test_globals1() {
+        let code = "static int count; const int table[] = {1, 2}; int * const p = 0; int main() { return count; } volatile int flag = 1;";
+        let program = parse(code).unwrap();
+        assert_eq!(program.functions.len(), 1);
+        let globals = program
+            .globals
+            .iter()
+            .map(|g| g.deparse())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            globals,
+            vec![
+                "static int count;",
+                "const int table[2] = {1, 2};",
+                "int* const p = 0;",
+                "volatile int flag = 1;",
+            ]
+        );
+        assert!(program.globals[0].is_static);
+        assert!(program.globals[1].ty.is_const());
+        assert_eq!(
+            program.globals[2].ty,
+            Type::Const(Box::new(Type::Pointer(Box::new(Type::Int))))
+        );
+        assert!(parse("int x = 1 int main() { return x; }").is_err());
+    }
+
+    #[test]
+    fn test_static_function1() {
+        let code =
+            "static int count; static int f(void) { return count; } static void g(int x); int n;";
+        let program = parse(code).unwrap();
+        assert_eq!(program.functions.len(), 2);
+        assert!(program.functions[0].is_static);
+        assert!(program.functions[1].is_static && program.functions[1].prototype);
+        assert_eq!(
+            program.deparse(),
+            "static int count;\nstatic int f() {\nreturn count ;\n}\nstatic void g(int x);\nint n;"
+        );
+    }
+
+    #[test]
+    fn test_int_types1() {
+        let code = "int main() { unsigned char a; signed char b; short int c; unsigned d; long e; unsigned long int f; uint8_t g; int16_t h; uint32_t i; return 0; }";
+        let program = parse(code).unwrap();
+        let types = program.functions[0]
+            .exprs
+            .iter()
+            .filter_map(|e| match e {
+                Expr::Decl { ty, .. } => Some(ty.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                Type::UChar,
+                Type::Char,
+                Type::Short,
+                Type::UInt,
+                Type::Long,
+                Type::ULong,
+                Type::UChar,
+                Type::Short,
+                Type::UInt,
+            ]
+        );
+        assert_eq!(Type::common(&Type::UChar, &Type::Short), Type::Int);
+        assert_eq!(Type::common(&Type::Int, &Type::UInt), Type::UInt);
+        assert_eq!(Type::common(&Type::Long, &Type::UInt), Type::ULong);
+        assert_eq!(
+            Type::arithmetic(Op::Shr, &Type::Int, &Type::UInt),
+            Type::Int
+        );
+        assert_eq!(Type::arithmetic(Op::Lt, &Type::UInt, &Type::Int), Type::Int);
+    }
+
+    #[test]
+    fn test_spans1() {
+        let code = "static int g = 1;\nint f(int x) {\n  return x + 10;\n}";
+        let program = parse(code).unwrap();
+        let function = &program.functions[0];
+        let text = |span: Span| &code[span.start..span.end];
+        assert_eq!(text(program.globals[0].span), "static int g = 1;");
+        assert_eq!(text(function.span), "int f(int x)");
+        assert_eq!(text(function.args[0].span), "int x");
+        assert_eq!(text(function.exprs[0].span()), "return x + 10;");
+        match &function.exprs[0] {
+            Expr::Return {
+                expr: Some(expr), ..
+            } => {
+                let at = code.find("x + 10").unwrap();
+                assert_eq!(
+                    **expr,
+                    Expr::BinOp {
+                        op: Op::Add,
+                        lhs: Box::new(Expr::Var {
+                            name: "x".to_string(),
+                            span: Span::new(at, at + 1),
+                        }),
+                        rhs: Box::new(Expr::Int {
+                            value: 10,
+                            span: Span::new(at + 4, at + 6),
+                        }),
+                        span: Span::new(at, at + 6),
+                    }
+                );
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_span1() {
+        let code = "int main() { return 0; }\n\nvoid f(int x {}";
+        let errors = parse(code).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].expected, vec![")".to_string(), ",".to_string()]);
+        assert_eq!(errors[0].span.start, code.rfind('{').unwrap());
+    }
+
+    #[test]
+    fn test_expected_set1() {
+        let code = "int main() { if (a) { return 1 } }";
+        let errors = parse(code).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].to_string(), "error: expected `;`");
+        assert_eq!(errors[0].span.start, code.rfind("} }").unwrap());
+        // an lvalue could be assigned to, but no operator is suggested
+        // after it any more than after a constant
+        let errors = parse("int main() { int a[2]; a[0] a[1]; return 0; }").unwrap_err();
+        assert_eq!(errors[0].to_string(), "error: expected `;`");
+    }
+
+    #[test]
+    fn test_recovery1() {
+        let code = "int main() {\n  int a = 1 +;\n  ) ;\n  while (a { a = 0; }\n  return a;\n}\nint g( { }\nint h() { return 0 }";
+        let errors = parse(code).unwrap_err();
+        let lines = errors
+            .iter()
+            .map(|e| code[..e.span.start].matches('\n').count() + 1)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 3, 4, 7, 8]);
+        assert_eq!(errors[0].to_string(), "error: expected `expression`");
+        assert_eq!(errors[1].to_string(), "error: expected `statement`");
+    }
+
     #[test]
     fn test_prop1() {
         fn prop1(program: Program) -> bool {
             let code = program.deparse();
-            let parsed = parse(&code).unwrap();
+            let parsed = without_spans(parse(&code).unwrap());
             codegen_code(&code, &program);
             parsed == program
         }
         QuickCheck::new()
-            .gen(Gen::new(2))
+            .rng(Gen::new(2))
             .tests(10)
             .quickcheck(prop1 as fn(Program) -> bool)
     }
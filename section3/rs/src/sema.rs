@@ -0,0 +1,1405 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use super::constants::{Op, UnaryOp};
+use super::parser::{Deparse, Expr, Function, Global, Item, Program, Type};
+use super::span::Span;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SemaError {
+    UndeclaredVariable {
+        name: String,
+        span: Span,
+    },
+    UndeclaredFunction {
+        name: String,
+        span: Span,
+    },
+    Redeclared {
+        name: String,
+        span: Span,
+    },
+    RedefinedFunction {
+        name: String,
+        span: Span,
+    },
+    ConflictingTypes {
+        name: String,
+        span: Span,
+    },
+    ArgCount {
+        name: String,
+        expected: usize,
+        got: usize,
+        span: Span,
+    },
+    Unused {
+        name: String,
+        span: Span,
+    },
+    VoidVariable {
+        name: String,
+        span: Span,
+    },
+    VoidValue {
+        expr: String,
+        span: Span,
+    },
+    NotAnLvalue {
+        expr: String,
+        span: Span,
+    },
+    ReturnValueInVoid {
+        name: String,
+        span: Span,
+    },
+    ReturnWithoutValue {
+        name: String,
+        span: Span,
+    },
+    MissingReturn {
+        name: String,
+        span: Span,
+    },
+    // `break` or `continue` outside of a loop
+    OutsideLoop {
+        keyword: String,
+        span: Span,
+    },
+    // `case` or `default` anywhere but directly in a switch body
+    OutsideSwitch {
+        keyword: String,
+        span: Span,
+    },
+    NonConstantCase {
+        expr: String,
+        span: Span,
+    },
+    DuplicateCase {
+        value: i32,
+        span: Span,
+    },
+    DuplicateDefault {
+        span: Span,
+    },
+    InvalidOperands {
+        op: String,
+        lhs: Type,
+        rhs: Type,
+        span: Span,
+    },
+    InvalidOperand {
+        op: String,
+        ty: Type,
+        span: Span,
+    },
+    NotAssignable {
+        expr: String,
+        span: Span,
+    },
+    IndexOutOfBounds {
+        index: i32,
+        len: u32,
+        span: Span,
+    },
+    ExcessElements {
+        span: Span,
+    },
+    InvalidInitializer {
+        name: String,
+        span: Span,
+    },
+    NonConstantInitializer {
+        name: String,
+        span: Span,
+    },
+    ReadOnly {
+        expr: String,
+        span: Span,
+    },
+}
+
+impl SemaError {
+    // warnings are reported but do not stop compilation
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            SemaError::Unused { .. }
+                | SemaError::MissingReturn { .. }
+                | SemaError::IndexOutOfBounds { .. }
+        )
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            SemaError::UndeclaredVariable { span, .. }
+            | SemaError::UndeclaredFunction { span, .. }
+            | SemaError::Redeclared { span, .. }
+            | SemaError::RedefinedFunction { span, .. }
+            | SemaError::ConflictingTypes { span, .. }
+            | SemaError::ArgCount { span, .. }
+            | SemaError::Unused { span, .. }
+            | SemaError::VoidVariable { span, .. }
+            | SemaError::VoidValue { span, .. }
+            | SemaError::NotAnLvalue { span, .. }
+            | SemaError::ReturnValueInVoid { span, .. }
+            | SemaError::ReturnWithoutValue { span, .. }
+            | SemaError::MissingReturn { span, .. }
+            | SemaError::OutsideLoop { span, .. }
+            | SemaError::OutsideSwitch { span, .. }
+            | SemaError::NonConstantCase { span, .. }
+            | SemaError::DuplicateCase { span, .. }
+            | SemaError::DuplicateDefault { span }
+            | SemaError::InvalidOperands { span, .. }
+            | SemaError::InvalidOperand { span, .. }
+            | SemaError::NotAssignable { span, .. }
+            | SemaError::IndexOutOfBounds { span, .. }
+            | SemaError::ExcessElements { span }
+            | SemaError::InvalidInitializer { span, .. }
+            | SemaError::NonConstantInitializer { span, .. }
+            | SemaError::ReadOnly { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for SemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemaError::UndeclaredVariable { name, .. } => {
+                write!(f, "error: use of undeclared variable `{}`", name)
+            }
+            SemaError::UndeclaredFunction { name, .. } => {
+                write!(f, "error: call to undeclared function `{}`", name)
+            }
+            SemaError::Redeclared { name, .. } => write!(f, "error: redeclaration of `{}`", name),
+            SemaError::RedefinedFunction { name, .. } => {
+                write!(f, "error: redefinition of function `{}`", name)
+            }
+            SemaError::ConflictingTypes { name, .. } => {
+                write!(f, "error: conflicting types for function `{}`", name)
+            }
+            SemaError::ArgCount {
+                name,
+                expected,
+                got,
+                ..
+            } => write!(
+                f,
+                "error: function `{}` takes {} arguments but {} were given",
+                name, expected, got
+            ),
+            SemaError::Unused { name, .. } => write!(f, "warning: unused variable `{}`", name),
+            SemaError::VoidVariable { name, .. } => {
+                write!(f, "error: variable `{}` declared void", name)
+            }
+            SemaError::VoidValue { expr, .. } => {
+                write!(f, "error: void value of `{}` used as a value", expr)
+            }
+            SemaError::NotAnLvalue { expr, .. } => {
+                write!(f, "error: `{}` is not an lvalue", expr)
+            }
+            SemaError::ReturnValueInVoid { name, .. } => {
+                write!(f, "error: void function `{}` returns a value", name)
+            }
+            SemaError::ReturnWithoutValue { name, .. } => {
+                write!(
+                    f,
+                    "error: non-void function `{}` returns without a value",
+                    name
+                )
+            }
+            SemaError::MissingReturn { name, .. } => write!(
+                f,
+                "warning: control reaches end of non-void function `{}`",
+                name
+            ),
+            SemaError::OutsideLoop { keyword, .. } if keyword == "break" => {
+                write!(f, "error: `break` outside of a loop or switch")
+            }
+            SemaError::OutsideLoop { keyword, .. } => {
+                write!(f, "error: `{}` outside of a loop", keyword)
+            }
+            SemaError::OutsideSwitch { keyword, .. } => {
+                write!(f, "error: `{}` label not directly in a switch", keyword)
+            }
+            SemaError::NonConstantCase { expr, .. } => {
+                write!(f, "error: case label `{}` is not a constant", expr)
+            }
+            SemaError::DuplicateCase { value, .. } => {
+                write!(f, "error: duplicate case value `{}`", value)
+            }
+            SemaError::DuplicateDefault { .. } => {
+                write!(f, "error: multiple default labels in one switch")
+            }
+            SemaError::InvalidOperands { op, lhs, rhs, .. } => write!(
+                f,
+                "error: invalid operands to `{}` (`{}` and `{}`)",
+                op, lhs, rhs
+            ),
+            SemaError::InvalidOperand { op, ty, .. } => {
+                write!(f, "error: invalid operand to `{}` (`{}`)", op, ty)
+            }
+            SemaError::NotAssignable { expr, .. } => {
+                write!(f, "error: array `{}` is not assignable", expr)
+            }
+            SemaError::IndexOutOfBounds { index, len, .. } => write!(
+                f,
+                "warning: index `{}` is out of bounds for an array of {} elements",
+                index, len
+            ),
+            SemaError::ExcessElements { .. } => {
+                write!(f, "error: excess elements in array initializer")
+            }
+            SemaError::InvalidInitializer { name, .. } => {
+                write!(f, "error: invalid initializer for `{}`", name)
+            }
+            SemaError::NonConstantInitializer { name, .. } => {
+                write!(f, "error: initializer for `{}` is not a constant", name)
+            }
+            SemaError::ReadOnly { expr, .. } => {
+                write!(f, "error: cannot assign to `{}`, it is const", expr)
+            }
+        }
+    }
+}
+
+struct Local {
+    ty: Type,
+    used: bool,
+    param: bool,
+    span: Span,
+}
+
+// qualifiers on a parameter itself do not change the signature, as in
+// `int f(int x)` and `int f(const int x)`
+struct Signature {
+    params: Vec<Type>,
+    ret_type: Type,
+}
+
+impl Signature {
+    fn of(func: &Function) -> Signature {
+        Signature {
+            params: func
+                .args
+                .iter()
+                .map(|arg| arg.ty.unqualified().clone())
+                .collect(),
+            ret_type: func.ret_type.clone(),
+        }
+    }
+}
+
+struct Sema {
+    functions: HashMap<String, Signature>,
+    func: String,
+    ret_type: Type,
+    scopes: Vec<HashMap<String, Local>>,
+    // how many loops and switches enclose the current statement
+    loops: usize,
+    switches: usize,
+    errors: Vec<SemaError>,
+}
+
+impl Sema {
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().expect("unbalanced scopes");
+        let mut unused = scope
+            .into_iter()
+            .filter(|(_, local)| !local.used && !local.param)
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(_, local)| local.span.start);
+        for (name, local) in unused {
+            let span = local.span;
+            self.errors.push(SemaError::Unused { name, span });
+        }
+    }
+
+    fn declare(&mut self, name: &str, ty: &Type, param: bool, span: Span) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("declaration outside of any scope");
+        if scope.contains_key(name) {
+            self.errors.push(SemaError::Redeclared {
+                name: name.to_string(),
+                span,
+            });
+            return;
+        }
+        let local = Local {
+            ty: ty.clone(),
+            used: false,
+            param,
+            span,
+        };
+        scope.insert(name.to_string(), local);
+    }
+
+    fn use_var(&mut self, name: &str, span: Span) -> Type {
+        match self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name)) {
+            Some(local) => {
+                local.used = true;
+                local.ty.clone()
+            }
+            None => {
+                self.errors.push(SemaError::UndeclaredVariable {
+                    name: name.to_string(),
+                    span,
+                });
+                Type::Int
+            }
+        }
+    }
+
+    // the type of `lhs op rhs`, pointers only take part in pointer
+    // arithmetic and comparisons
+    fn check_binop(
+        &mut self,
+        op: Op,
+        lhs: (&Type, &Expr),
+        rhs: (&Type, &Expr),
+        span: Span,
+    ) -> Type {
+        let ((lhs, lhs_expr), (rhs, rhs_expr)) = (lhs, rhs);
+        let ty = match (op, lhs.pointee(), rhs.pointee()) {
+            (Op::And | Op::Or, _, _) => Some(Type::Int),
+            (_, None, None) => Some(Type::arithmetic(op, lhs, rhs)),
+            (Op::Add, Some(_), None) | (Op::Sub, Some(_), None) => Some(lhs.unqualified().clone()),
+            (Op::Add, None, Some(_)) => Some(rhs.unqualified().clone()),
+            (Op::Sub, Some(l), Some(r)) if compatible(l, r) => Some(Type::Int),
+            (Op::Eq | Op::Ne | Op::Le | Op::Ge | Op::Lt | Op::Gt, Some(l), Some(r))
+                if compatible(l, r) || l.is_void() || r.is_void() =>
+            {
+                Some(Type::Int)
+            }
+            // only the constant 0 compares with a pointer, as the null pointer
+            (Op::Eq | Op::Ne, Some(_), None) if rhs_expr.const_value() == Some(0) => {
+                Some(Type::Int)
+            }
+            (Op::Eq | Op::Ne, None, Some(_)) if lhs_expr.const_value() == Some(0) => {
+                Some(Type::Int)
+            }
+            _ => None,
+        };
+        ty.unwrap_or_else(|| {
+            self.errors.push(SemaError::InvalidOperands {
+                op: op.deparse(),
+                lhs: lhs.unqualified().clone(),
+                rhs: rhs.unqualified().clone(),
+                span,
+            });
+            Type::Int
+        })
+    }
+
+    // arrays are lvalues, but cannot be assigned to as a whole, nor can
+    // anything const
+    fn check_assignable(&mut self, lhs: &Expr, ty: Type) -> Type {
+        if let Type::Array(..) = ty.unqualified() {
+            self.errors.push(SemaError::NotAssignable {
+                expr: lhs.deparse(),
+                span: lhs.span(),
+            });
+        } else if ty.is_const() {
+            self.errors.push(SemaError::ReadOnly {
+                expr: lhs.deparse(),
+                span: lhs.span(),
+            });
+        }
+        ty
+    }
+
+    // an array takes a braced list with at most one initializer per
+    // element, anything else takes an expression
+    fn check_initializer(&mut self, ty: &Type, init: &Expr, name: &str) {
+        match (ty.unqualified(), init) {
+            (Type::Array(elem, len), Expr::InitList { values, .. }) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i == *len as usize {
+                        self.errors
+                            .push(SemaError::ExcessElements { span: value.span() });
+                    }
+                    self.check_initializer(elem, value, name);
+                }
+            }
+            (Type::Array(..), _) | (_, Expr::InitList { .. }) => {
+                self.errors.push(SemaError::InvalidInitializer {
+                    name: name.to_string(),
+                    span: init.span(),
+                });
+            }
+            (_, init) => {
+                init.check_value(self);
+            }
+        }
+    }
+
+    // what a global starts out as is fixed before the program runs
+    fn check_constant(&mut self, init: &Expr, name: &str) {
+        match init {
+            Expr::InitList { values, .. } => {
+                for value in values {
+                    self.check_constant(value, name);
+                }
+            }
+            init if init.const_value().is_none() && !self.is_address_constant(init) => {
+                self.errors.push(SemaError::NonConstantInitializer {
+                    name: name.to_string(),
+                    span: init.span(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // an object that lives as long as the program, a global or an element
+    // of a global array, which are all there is at file scope
+    fn static_object(&self, expr: &Expr) -> Option<Type> {
+        match expr {
+            Expr::Var { name, .. } => self
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(name))
+                .map(|local| local.ty.clone()),
+            Expr::Index { base, index, .. } => {
+                match (self.static_object(base)?.unqualified(), index.const_value()) {
+                    (Type::Array(elem, _), Some(_)) => Some((**elem).clone()),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // an address the linker fills in, as in `&x`, `&a[1]` or an array `a`
+    fn is_address_constant(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Unary {
+                op: UnaryOp::AddrOf,
+                expr,
+                ..
+            } => self.static_object(expr).is_some(),
+            expr => matches!(
+                self.static_object(expr).as_ref().map(Type::unqualified),
+                Some(Type::Array(..))
+            ),
+        }
+    }
+
+    fn check_block(&mut self, exprs: &[Expr]) {
+        self.push_scope();
+        for expr in exprs {
+            expr.check(self);
+        }
+        self.pop_scope();
+    }
+
+    fn check_loop_body(&mut self, body: &[Expr]) {
+        self.loops += 1;
+        self.check_block(body);
+        self.loops -= 1;
+    }
+
+    fn check_switch_body(&mut self, body: &[Expr]) {
+        let mut values = HashSet::new();
+        let mut has_default = false;
+        self.push_scope();
+        for expr in body {
+            match expr {
+                Expr::Case { value, span } => match value.const_value() {
+                    Some(val) if !values.insert(val) => {
+                        self.errors.push(SemaError::DuplicateCase {
+                            value: val,
+                            span: *span,
+                        });
+                    }
+                    Some(_) => {}
+                    None => {
+                        value.check_value(self);
+                        self.errors.push(SemaError::NonConstantCase {
+                            expr: value.deparse(),
+                            span: value.span(),
+                        });
+                    }
+                },
+                Expr::Default { span } if has_default => {
+                    self.errors
+                        .push(SemaError::DuplicateDefault { span: *span });
+                }
+                Expr::Default { .. } => has_default = true,
+                expr => {
+                    expr.check(self);
+                }
+            }
+        }
+        self.pop_scope();
+    }
+
+    fn check_jump(&mut self, keyword: &str, enclosing: usize, span: Span) {
+        if enclosing == 0 {
+            self.errors.push(SemaError::OutsideLoop {
+                keyword: keyword.to_string(),
+                span,
+            });
+        }
+    }
+}
+
+// pointers to the same type, whatever its qualifiers
+fn compatible(lhs: &Type, rhs: &Type) -> bool {
+    lhs.unqualified() == rhs.unqualified()
+}
+
+// a variable of this type would hold no value, `void x[4]` neither
+fn is_void_object(ty: &Type) -> bool {
+    match ty.unqualified() {
+        Type::Array(elem, _) => is_void_object(elem),
+        ty => ty.is_void(),
+    }
+}
+
+impl Expr {
+    // check an expression whose value is needed, an array is a pointer to
+    // its first element
+    fn check_value(&self, sema: &mut Sema) -> Type {
+        let ty = self.check(sema);
+        self.non_void(ty, sema).decay()
+    }
+
+    fn non_void(&self, ty: Type, sema: &mut Sema) -> Type {
+        if ty.is_void() {
+            sema.errors.push(SemaError::VoidValue {
+                expr: self.deparse(),
+                span: self.span(),
+            });
+            return Type::Int;
+        }
+        ty
+    }
+
+    // an expression naming a storage location, as assigned to or addressed
+    fn check_lvalue(&self, sema: &mut Sema) -> Type {
+        if !matches!(
+            self,
+            Expr::Var { .. } | Expr::Deref { .. } | Expr::Index { .. }
+        ) {
+            sema.errors.push(SemaError::NotAnLvalue {
+                expr: self.deparse(),
+                span: self.span(),
+            });
+        }
+        let ty = self.check(sema);
+        self.non_void(ty, sema)
+    }
+
+    // `&a[len]` is the address one past the end, so bounds are only
+    // checked when the element itself is accessed
+    fn check_index(&self, bounds: bool, sema: &mut Sema) -> Type {
+        let Expr::Index { base, index, span } = self else {
+            return self.check(sema);
+        };
+        let base_ty = base.check(sema);
+        let index_ty = index.check_value(sema);
+        if let (true, Type::Array(_, len), Some(i)) =
+            (bounds, base_ty.unqualified(), index.const_value())
+        {
+            if u32::try_from(i).map_or(true, |i| i >= *len) {
+                sema.errors.push(SemaError::IndexOutOfBounds {
+                    index: i,
+                    len: *len,
+                    span: index.span(),
+                });
+            }
+        }
+        let ptr_ty = base.non_void(base_ty, sema).decay();
+        match (ptr_ty.pointee(), index_ty.pointee()) {
+            (Some(to), None) if !to.is_void() => to.clone(),
+            _ => {
+                sema.errors.push(SemaError::InvalidOperands {
+                    op: "[]".to_string(),
+                    lhs: ptr_ty.unqualified().clone(),
+                    rhs: index_ty.unqualified().clone(),
+                    span: *span,
+                });
+                Type::Int
+            }
+        }
+    }
+
+    // returns the type of the value the expression produces, statements are void
+    fn check(&self, sema: &mut Sema) -> Type {
+        match self {
+            Expr::ExprStmt { expr, .. } => {
+                expr.check(sema);
+                Type::Void
+            }
+            Expr::Int { value, .. } => Type::literal(*value),
+            Expr::BinOp { lhs, rhs, op, span } => {
+                let lhs_ty = lhs.check_value(sema);
+                let rhs_ty = rhs.check_value(sema);
+                sema.check_binop(*op, (&lhs_ty, lhs), (&rhs_ty, rhs), *span)
+            }
+            Expr::Return { expr, span } => {
+                match expr {
+                    Some(expr) => {
+                        expr.check_value(sema);
+                        if sema.ret_type == Type::Void {
+                            sema.errors.push(SemaError::ReturnValueInVoid {
+                                name: sema.func.clone(),
+                                span: *span,
+                            });
+                        }
+                    }
+                    None if sema.ret_type != Type::Void => {
+                        sema.errors.push(SemaError::ReturnWithoutValue {
+                            name: sema.func.clone(),
+                            span: *span,
+                        });
+                    }
+                    None => {}
+                }
+                Type::Void
+            }
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                cond.check_value(sema);
+                sema.check_block(then);
+                sema.check_block(otherwise);
+                Type::Void
+            }
+            Expr::Decl {
+                ty,
+                name,
+                init,
+                span,
+            } => {
+                if let Some(init) = init {
+                    sema.check_initializer(ty, init, name);
+                }
+                if is_void_object(ty) {
+                    sema.errors.push(SemaError::VoidVariable {
+                        name: name.clone(),
+                        span: *span,
+                    });
+                }
+                sema.declare(name, ty, false, *span);
+                Type::Void
+            }
+            Expr::Var { name, span } => sema.use_var(name, *span),
+            // there are no casts, ints and pointers convert on assignment
+            Expr::Assign { lhs, rhs, .. } => {
+                let ty = match &**lhs {
+                    // storing to a variable does not count as using it
+                    Expr::Var { name, span } => {
+                        match sema.scopes.iter().rev().find_map(|s| s.get(name)) {
+                            Some(local) => local.ty.clone(),
+                            None => sema.use_var(name, *span),
+                        }
+                    }
+                    lhs => lhs.check_lvalue(sema),
+                };
+                let ty = sema.check_assignable(lhs, ty);
+                rhs.check_value(sema);
+                ty.decay()
+            }
+            Expr::CompoundAssign { lhs, rhs, op, span } => {
+                let ty = lhs.check_lvalue(sema);
+                let ty = sema.check_assignable(lhs, ty).decay();
+                let rhs_ty = rhs.check_value(sema);
+                sema.check_binop(*op, (&ty, lhs), (&rhs_ty, rhs), *span);
+                ty
+            }
+            Expr::IncDec { expr, .. } => {
+                let ty = expr.check_lvalue(sema);
+                sema.check_assignable(expr, ty).decay()
+            }
+            // the bounds of an array are only known for a constant index
+            Expr::Index { .. } => self.check_index(true, sema),
+            // only found in a declaration, which checks it against the type
+            Expr::InitList { values, .. } => {
+                for value in values {
+                    value.check(sema);
+                }
+                Type::Void
+            }
+            // `*` on an int is an access to a fixed address, volatile as it
+            // is mostly memory mapped IO
+            Expr::Deref { addr, span } => {
+                let ty = addr.check_value(sema);
+                match ty.pointee() {
+                    Some(to) if to.is_void() => {
+                        sema.errors.push(SemaError::InvalidOperand {
+                            op: "*".to_string(),
+                            ty: ty.unqualified().clone(),
+                            span: *span,
+                        });
+                        Type::Int
+                    }
+                    Some(to) => to.clone(),
+                    None => Type::Volatile(Box::new(Type::Int)),
+                }
+            }
+            Expr::Unary {
+                op: UnaryOp::AddrOf,
+                expr,
+                ..
+            } => Type::Pointer(Box::new(match &**expr {
+                Expr::Index { .. } => expr.check_index(false, sema),
+                _ => expr.check_lvalue(sema),
+            })),
+            Expr::Unary { expr, op, span } => {
+                let ty = expr.check_value(sema);
+                if *op != UnaryOp::Not && ty.pointee().is_some() {
+                    sema.errors.push(SemaError::InvalidOperand {
+                        op: op.deparse(),
+                        ty: ty.unqualified().clone(),
+                        span: *span,
+                    });
+                }
+                match op {
+                    UnaryOp::Not => Type::Int,
+                    _ => ty.promoted(),
+                }
+            }
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                cond.check_value(sema);
+                let then_ty = then.check_value(sema);
+                let otherwise_ty = otherwise.check_value(sema);
+                match (then_ty.pointee(), otherwise_ty.pointee()) {
+                    (Some(_), _) => then_ty.unqualified().clone(),
+                    (_, Some(_)) => otherwise_ty.unqualified().clone(),
+                    _ => Type::common(&then_ty, &otherwise_ty),
+                }
+            }
+            Expr::While { cond, body, .. } | Expr::DoWhile { body, cond, .. } => {
+                cond.check_value(sema);
+                sema.check_loop_body(body);
+                Type::Void
+            }
+            Expr::For {
+                init,
+                cond,
+                step,
+                body,
+                ..
+            } => {
+                // a declaration in `init` is scoped to the loop
+                sema.push_scope();
+                if let Some(init) = init {
+                    init.check(sema);
+                }
+                if let Some(cond) = cond {
+                    cond.check_value(sema);
+                }
+                if let Some(step) = step {
+                    step.check(sema);
+                }
+                sema.check_loop_body(body);
+                sema.pop_scope();
+                Type::Void
+            }
+            Expr::Break { span } => {
+                sema.check_jump("break", sema.loops + sema.switches, *span);
+                Type::Void
+            }
+            Expr::Continue { span } => {
+                sema.check_jump("continue", sema.loops, *span);
+                Type::Void
+            }
+            Expr::Switch { cond, body, .. } => {
+                cond.check_value(sema);
+                sema.switches += 1;
+                sema.check_switch_body(body);
+                sema.switches -= 1;
+                Type::Void
+            }
+            // labels directly in a switch body are checked by the switch
+            Expr::Case { span, .. } => {
+                sema.errors.push(SemaError::OutsideSwitch {
+                    keyword: "case".to_string(),
+                    span: *span,
+                });
+                Type::Void
+            }
+            Expr::Default { span } => {
+                sema.errors.push(SemaError::OutsideSwitch {
+                    keyword: "default".to_string(),
+                    span: *span,
+                });
+                Type::Void
+            }
+            Expr::Call { name, args, span } => {
+                let ret_type = match sema.functions.get(name) {
+                    Some(sig) => {
+                        if sig.params.len() != args.len() {
+                            sema.errors.push(SemaError::ArgCount {
+                                name: name.clone(),
+                                expected: sig.params.len(),
+                                got: args.len(),
+                                span: *span,
+                            });
+                        }
+                        sig.ret_type.clone()
+                    }
+                    None => {
+                        sema.errors.push(SemaError::UndeclaredFunction {
+                            name: name.clone(),
+                            span: *span,
+                        });
+                        Type::Int
+                    }
+                };
+                for arg in args {
+                    arg.check_value(sema);
+                }
+                ret_type
+            }
+        }
+    }
+}
+
+// whether control can never fall off the end of a block
+fn returns(exprs: &[Expr]) -> bool {
+    exprs.iter().any(|expr| match expr {
+        Expr::Return { .. } => true,
+        Expr::If {
+            then, otherwise, ..
+        } => returns(then) && returns(otherwise),
+        // loops that only a `break` can leave
+        Expr::While { cond, body, .. } => always(cond) && !breaks(body),
+        Expr::For { cond, body, .. } => cond.as_deref().is_none_or(always) && !breaks(body),
+        Expr::DoWhile { body, cond, .. } => (returns(body) || always(cond)) && !breaks(body),
+        // with a `default` every value reaches a label, and from the last
+        // label on control has to return
+        Expr::Switch { body, .. } => {
+            let is_label = |e: &Expr| matches!(e, Expr::Case { .. } | Expr::Default { .. });
+            let after_labels = body.iter().rposition(is_label).map_or(0, |i| i + 1);
+            body.iter().any(|e| matches!(e, Expr::Default { .. }))
+                && !breaks(body)
+                && returns(&body[after_labels..])
+        }
+        _ => false,
+    })
+}
+
+fn always(cond: &Expr) -> bool {
+    matches!(cond, Expr::Int { value, .. } if *value != 0)
+}
+
+// whether a `break` leaves the loop or switch with this body, nested loops
+// and switches catch theirs
+fn breaks(exprs: &[Expr]) -> bool {
+    exprs.iter().any(|expr| match expr {
+        Expr::Break { .. } => true,
+        Expr::If {
+            then, otherwise, ..
+        } => breaks(then) || breaks(otherwise),
+        _ => false,
+    })
+}
+
+impl Function {
+    fn check(&self, sema: &mut Sema) {
+        sema.func = self.name.clone();
+        sema.ret_type = self.ret_type.clone();
+        // parameters share a scope with the outermost block of the body
+        sema.push_scope();
+        for arg in &self.args {
+            if arg.ty.is_void() {
+                sema.errors.push(SemaError::VoidVariable {
+                    name: arg.name.clone(),
+                    span: arg.span,
+                });
+            }
+            sema.declare(&arg.name, &arg.ty, true, arg.span);
+        }
+        for expr in &self.exprs {
+            expr.check(sema);
+        }
+        sema.pop_scope();
+        // falling off the end of main returns 0
+        if self.ret_type != Type::Void && self.name != "main" && !returns(&self.exprs) {
+            sema.errors.push(SemaError::MissingReturn {
+                name: self.name.clone(),
+                span: self.span,
+            });
+        }
+    }
+}
+
+impl Global {
+    fn check(&self, sema: &mut Sema) {
+        if let Some(init) = &self.init {
+            sema.check_initializer(&self.ty, init, &self.name);
+            sema.check_constant(init, &self.name);
+        }
+        if is_void_object(&self.ty) {
+            sema.errors.push(SemaError::VoidVariable {
+                name: self.name.clone(),
+                span: self.span,
+            });
+        }
+        if sema.functions.contains_key(&self.name) {
+            sema.errors.push(SemaError::Redeclared {
+                name: self.name.clone(),
+                span: self.span,
+            });
+        }
+        sema.declare(&self.name, &self.ty, false, self.span);
+    }
+}
+
+impl Program {
+    fn check(&self, sema: &mut Sema) {
+        // every signature is visible in every body, as in codegen
+        let mut defined = HashSet::new();
+        for func in &self.functions {
+            let sig = Signature::of(func);
+            match sema.functions.get(&func.name) {
+                Some(existing)
+                    if existing.params != sig.params || existing.ret_type != sig.ret_type =>
+                {
+                    sema.errors.push(SemaError::ConflictingTypes {
+                        name: func.name.clone(),
+                        span: func.span,
+                    });
+                }
+                _ => {
+                    sema.functions.insert(func.name.clone(), sig);
+                }
+            }
+            if !func.prototype && !defined.insert(&func.name) {
+                sema.errors.push(SemaError::RedefinedFunction {
+                    name: func.name.clone(),
+                    span: func.span,
+                });
+            }
+        }
+        // the file scope is never popped, globals may be used from other
+        // files and are not reported as unused
+        sema.push_scope();
+        for item in self.items() {
+            match item {
+                Item::Global(global) => global.check(sema),
+                Item::Function(func) if !func.prototype => func.check(sema),
+                Item::Function(_) => {}
+            }
+        }
+    }
+}
+
+// resolve names in a parsed program, returning every error and warning found
+pub fn check(program: &Program) -> Vec<SemaError> {
+    let mut sema = Sema {
+        functions: HashMap::new(),
+        func: String::new(),
+        ret_type: Type::Void,
+        scopes: Vec::new(),
+        loops: 0,
+        switches: 0,
+        errors: Vec::new(),
+    };
+    program.check(&mut sema);
+    sema.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn check_code(code: &str) -> Vec<SemaError> {
+        check(&parse(code).unwrap())
+    }
+
+    // where the first `text` in `code` is
+    fn at(code: &str, text: &str) -> Span {
+        let start = code.find(text).unwrap();
+        Span::new(start, start + text.len())
+    }
+
+    // where the first `text` after the first `marker` is
+    fn after(code: &str, marker: &str, text: &str) -> Span {
+        let from = code.find(marker).unwrap() + marker.len();
+        let span = at(&code[from..], text);
+        Span::new(from + span.start, from + span.end)
+    }
+
+    #[test]
+    fn test_clean() {
+        let code = "int f(int x); int main() { int a = 1; if (a) { int a = 2; return f(a); } else { return 0; } } int f(int x) { return x; }";
+        assert_eq!(check_code(code), vec![]);
+    }
+
+    #[test]
+    fn test_undeclared() {
+        let code = "int main() { int count = 1; return cuont + g(count); }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::UndeclaredVariable {
+                    name: "cuont".to_string(),
+                    span: at(code, "cuont"),
+                },
+                SemaError::UndeclaredFunction {
+                    name: "g".to_string(),
+                    span: at(code, "g(count)"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redeclared() {
+        let code = "int f(int x, int x) { int y = x; int y; return y; } int f() { return 0; }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::ConflictingTypes {
+                    name: "f".to_string(),
+                    span: at(code, "int f()"),
+                },
+                SemaError::RedefinedFunction {
+                    name: "f".to_string(),
+                    span: at(code, "int f()"),
+                },
+                SemaError::Redeclared {
+                    name: "x".to_string(),
+                    span: after(code, "x, ", "int x"),
+                },
+                SemaError::Redeclared {
+                    name: "y".to_string(),
+                    span: at(code, "int y;"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_params() {
+        let code = "int f(int *p); int g(const int x); int main() { return 0; }
+            int f(int x) { return x; } int g(int x) { return x; }";
+        assert_eq!(
+            check_code(code),
+            vec![SemaError::ConflictingTypes {
+                name: "f".to_string(),
+                span: at(code, "int f(int x)"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_void() {
+        let code = "void put(int c) { *0x400 = c; return; } int get() { return; } void f() { int a = put(1); return put(2); } int g(int x) { put(x); if (x) { return 1; } } int main() { return get(); }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::ReturnWithoutValue {
+                    name: "get".to_string(),
+                    span: after(code, "get() { ", "return;"),
+                },
+                SemaError::VoidValue {
+                    expr: "put(1)".to_string(),
+                    span: at(code, "put(1)"),
+                },
+                SemaError::VoidValue {
+                    expr: "put(2)".to_string(),
+                    span: at(code, "put(2)"),
+                },
+                SemaError::ReturnValueInVoid {
+                    name: "f".to_string(),
+                    span: at(code, "return put(2);"),
+                },
+                SemaError::Unused {
+                    name: "a".to_string(),
+                    span: at(code, "int a = put(1);"),
+                },
+                SemaError::MissingReturn {
+                    name: "g".to_string(),
+                    span: at(code, "int g(int x)"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unused_and_arg_count() {
+        let code = "int f(int x) { return x; } int main() { int a; a = 2; while (1) { int b = 3; } return f(1, 2); }";
+        let errors = check_code(code);
+        assert_eq!(
+            errors,
+            vec![
+                SemaError::Unused {
+                    name: "b".to_string(),
+                    span: at(code, "int b = 3;"),
+                },
+                SemaError::ArgCount {
+                    name: "f".to_string(),
+                    expected: 1,
+                    got: 2,
+                    span: at(code, "f(1, 2)"),
+                },
+                SemaError::Unused {
+                    name: "a".to_string(),
+                    span: at(code, "int a;"),
+                },
+            ]
+        );
+        assert!(errors[0].is_warning());
+        assert!(!errors[1].is_warning());
+    }
+
+    #[test]
+    fn test_lvalues() {
+        let code = "int main() { int x; *(x + 4) = 1; 3 = 2; x += 1; (x + 1)++; return &x - &-x; }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::NotAnLvalue {
+                    expr: "3".to_string(),
+                    span: at(code, "3"),
+                },
+                SemaError::NotAnLvalue {
+                    expr: "(x + 1)".to_string(),
+                    span: at(code, "x + 1"),
+                },
+                SemaError::NotAnLvalue {
+                    expr: "(-x)".to_string(),
+                    span: at(code, "-x"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        let code = "int f() { while (1) { if (1) { break; } } }
+            int g() { for (;;) { while (1) { break; } } }
+            int main() { continue; for (int i = 0; i < 3; i++) { break; } return i; }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::MissingReturn {
+                    name: "f".to_string(),
+                    span: at(code, "int f()"),
+                },
+                SemaError::OutsideLoop {
+                    keyword: "continue".to_string(),
+                    span: at(code, "continue;"),
+                },
+                SemaError::UndeclaredVariable {
+                    name: "i".to_string(),
+                    span: after(code, "return ", "i"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_switch() {
+        let code = "int f(int x) { switch (x) { case 1: break; default: return 1; } }
+            int g(int x) { switch (x) { case 1: x++; default: return x; } }
+            int main(int x) {
+                switch (x) { case 1: case x: case 2 - 1: default: default: continue; }
+                case 3: return 0;
+            }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::MissingReturn {
+                    name: "f".to_string(),
+                    span: at(code, "int f(int x)"),
+                },
+                SemaError::NonConstantCase {
+                    expr: "x".to_string(),
+                    span: after(code, "1: case ", "x"),
+                },
+                SemaError::DuplicateCase {
+                    value: 1,
+                    span: at(code, "case 2 - 1:"),
+                },
+                SemaError::DuplicateDefault {
+                    span: after(code, "1: default: ", "default:"),
+                },
+                SemaError::OutsideLoop {
+                    keyword: "continue".to_string(),
+                    span: at(code, "continue;"),
+                },
+                SemaError::OutsideSwitch {
+                    keyword: "case".to_string(),
+                    span: at(code, "case 3:"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_arrays() {
+        let code = "int sum(int v[]) { return v[0]; }
+            int main() {
+                int a[3] = {1, 2, 3, 4}; int b[2] = 5; int m[2][2] = {{1, 2}, 3};
+                int *p = a; a = p; p[7] = 1; p = &a[3];
+                return sum(a) + a[3] + a[2] + m[1][0] + b[0] + 1[a];
+            }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::ExcessElements {
+                    span: at(code, "4"),
+                },
+                SemaError::InvalidInitializer {
+                    name: "b".to_string(),
+                    span: at(code, "5"),
+                },
+                SemaError::InvalidInitializer {
+                    name: "m".to_string(),
+                    span: after(code, "2}, ", "3"),
+                },
+                SemaError::NotAssignable {
+                    expr: "a".to_string(),
+                    span: after(code, "a; ", "a"),
+                },
+                SemaError::IndexOutOfBounds {
+                    index: 3,
+                    len: 3,
+                    span: after(code, "+ a[", "3"),
+                },
+                SemaError::InvalidOperands {
+                    op: "[]".to_string(),
+                    lhs: Type::Int,
+                    rhs: Type::Pointer(Box::new(Type::Int)),
+                    span: at(code, "1[a]"),
+                },
+            ]
+        );
+        assert_eq!(
+            check_code(code)[4].to_string(),
+            "warning: index `3` is out of bounds for an array of 3 elements"
+        );
+    }
+
+    #[test]
+    fn test_globals() {
+        let code = "int a = 1; static int b = a; const int k[2] = {1, 1 + 1}; int f;
+            int f() { return 0; }
+            int main() { int x = 1; const int y = 2; x = y; k[0] = 1; a++; return a + b + k[1] + x; }";
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::NonConstantInitializer {
+                    name: "b".to_string(),
+                    span: after(code, "b = ", "a"),
+                },
+                SemaError::Redeclared {
+                    name: "f".to_string(),
+                    span: at(code, "int f;"),
+                },
+                SemaError::ReadOnly {
+                    expr: "k[0]".to_string(),
+                    span: at(code, "k[0]"),
+                },
+            ]
+        );
+        assert_eq!(
+            check_code(code)[2].to_string(),
+            "error: cannot assign to `k[0]`, it is const"
+        );
+    }
+
+    #[test]
+    fn test_global_addresses() {
+        let code = "int x; int buf[4]; int grid[2][3]; int n = 2;
+            int *p = &x; int *q = buf; int *r = &buf[2]; int *s = grid[1]; int *t = &buf[n];
+            int main() { return *p + *q + *r + *s + *t; }";
+        assert_eq!(
+            check_code(code),
+            vec![SemaError::NonConstantInitializer {
+                name: "t".to_string(),
+                span: after(code, "t = ", "&buf[n]"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_global_order() {
+        let code = "int f() { return later; } int later = 1; int main() { return later; }";
+        assert_eq!(
+            check_code(code),
+            vec![SemaError::UndeclaredVariable {
+                name: "later".to_string(),
+                span: at(code, "later"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pointers() {
+        let code = "int main() {
+                int x; int *p = &x; char *s; void *v = p;
+                return (p + 1 == p) + (p - p) + (p + p) + (p < s) + (p == 0) + (p == 1) + -p + *v + (v == p);
+            }";
+        let int_ptr = Type::Pointer(Box::new(Type::Int));
+        assert_eq!(
+            check_code(code),
+            vec![
+                SemaError::InvalidOperands {
+                    op: "+".to_string(),
+                    lhs: int_ptr.clone(),
+                    rhs: int_ptr.clone(),
+                    span: at(code, "p + p"),
+                },
+                SemaError::InvalidOperands {
+                    op: "<".to_string(),
+                    lhs: int_ptr.clone(),
+                    rhs: Type::Pointer(Box::new(Type::Char)),
+                    span: at(code, "p < s"),
+                },
+                SemaError::InvalidOperands {
+                    op: "==".to_string(),
+                    lhs: int_ptr.clone(),
+                    rhs: Type::Int,
+                    span: at(code, "p == 1"),
+                },
+                SemaError::InvalidOperand {
+                    op: "-".to_string(),
+                    ty: int_ptr,
+                    span: at(code, "-p"),
+                },
+                SemaError::InvalidOperand {
+                    op: "*".to_string(),
+                    ty: Type::Pointer(Box::new(Type::Void)),
+                    span: after(code, "-p + ", "*v"),
+                },
+            ]
+        );
+        assert_eq!(
+            check_code(code)[1].to_string(),
+            "error: invalid operands to `<` (`int*` and `char*`)"
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let code = "int main() {\n  int a;\n  return cuont;\n}";
+        let spans = check_code(code)
+            .iter()
+            .map(|e| &code[e.span().start..e.span().end])
+            .collect::<Vec<_>>();
+        assert_eq!(spans, vec!["cuont", "int a;"]);
+    }
+}
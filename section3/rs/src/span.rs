@@ -0,0 +1,91 @@
+use std::fmt::Write;
+
+/// A byte range of the source code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    // the smallest span covering both
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Source code with the name it is reported under.
+pub struct Source<'a> {
+    pub name: &'a str,
+    pub code: &'a str,
+}
+
+impl<'a> Source<'a> {
+    /// The 1-based line and column of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.code.len());
+        let before = &self.code[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    /// Format a message as `file:line:col: message`, followed by the line
+    /// it points into with the span underlined.
+    pub fn render(&self, span: Span, message: &str) -> String {
+        let start = span.start.min(self.code.len());
+        let (line, col) = self.line_col(start);
+        let line_start = self.code[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.code[start..]
+            .find('\n')
+            .map_or(self.code.len(), |i| start + i);
+        let text = &self.code[line_start..line_end];
+        // keep tabs so the caret lines up with the text above it
+        let pad: String = self.code[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.code[start..span.end.clamp(start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        let mut out = format!("{}:{}:{}: {}\n", self.name, line, col, message);
+        let gutter = " ".repeat(line.to_string().len());
+        let _ = writeln!(out, "{} |", gutter);
+        let _ = writeln!(out, "{} | {}", line, text);
+        let _ = write!(out, "{} | {}{}", gutter, pad, "^".repeat(width));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let source = Source {
+            name: "a.c",
+            code: "int main() {\n  return x;\n}",
+        };
+        assert_eq!(source.line_col(0), (1, 1));
+        assert_eq!(source.line_col(22), (2, 10));
+        assert_eq!(source.line_col(100), (3, 2));
+    }
+
+    #[test]
+    fn test_render() {
+        let source = Source {
+            name: "a.c",
+            code: "int main() {\n\treturn foo;\n}",
+        };
+        assert_eq!(
+            source.render(Span::new(21, 24), "error: use of undeclared variable `foo`"),
+            "a.c:2:9: error: use of undeclared variable `foo`\n  |\n2 | \treturn foo;\n  | \t       ^^^"
+        );
+    }
+}